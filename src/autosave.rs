@@ -0,0 +1,131 @@
+use crate::Database;
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+// Flushes a changed database to disk, at most once per `interval`.
+pub struct Autosave {
+    path: String,
+    interval: Duration,
+    last_write: Option<Instant>,
+    // The database's generation when this file was last written. Tracked
+    // here rather than through the dirty flag, which a SAVE to another path
+    // clears.
+    written: u64,
+}
+
+impl Autosave {
+    pub fn new(path: &str, interval: Duration) -> Self {
+        Autosave {
+            path: path.to_string(),
+            interval,
+            last_write: None,
+            written: 0,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // Called after every command. Returns true if the file was written.
+    pub fn tick(&mut self, db: &mut Database) -> io::Result<bool> {
+        let due = self
+            .last_write
+            .is_none_or(|at| at.elapsed() >= self.interval);
+        if !db.changed_since(self.written) || !due {
+            return Ok(false);
+        }
+        self.flush(db)
+    }
+
+    // Writes pending changes right away, ignoring the interval.
+    pub fn flush(&mut self, db: &mut Database) -> io::Result<bool> {
+        if !db.changed_since(self.written) {
+            return Ok(false);
+        }
+        db.save(&self.path)?;
+        self.last_write = Some(Instant::now());
+        self.written = db.generation();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DbValue;
+    use crate::testing::temp_path;
+    use std::fs;
+
+    #[test]
+    fn writes_after_a_change() {
+        let path = temp_path("autosave-change");
+        let mut autosave = Autosave::new(&path, Duration::ZERO);
+        let mut db = Database::new();
+        db.set("k".into(), DbValue::from_i64(1));
+
+        assert!(autosave.tick(&mut db).unwrap());
+        assert_eq!(Database::load(&path).unwrap().count(), 1);
+        db.set("j".into(), DbValue::from_i64(2));
+        assert!(autosave.tick(&mut db).unwrap());
+        assert_eq!(Database::load(&path).unwrap().count(), 2);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn skips_the_write_when_nothing_changed() {
+        let path = temp_path("autosave-unchanged");
+        let mut autosave = Autosave::new(&path, Duration::ZERO);
+        let mut db = Database::new();
+        assert!(!autosave.tick(&mut db).unwrap());
+        assert!(fs::metadata(&path).is_err());
+
+        db.set("k".into(), DbValue::from_i64(1));
+        autosave.tick(&mut db).unwrap();
+        fs::remove_file(&path).unwrap();
+        db.get("k");
+        assert!(!autosave.tick(&mut db).unwrap());
+        assert!(!autosave.flush(&mut db).unwrap());
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn waits_out_the_interval_until_flushed() {
+        let path = temp_path("autosave-interval");
+        let mut autosave = Autosave::new(&path, Duration::from_secs(3600));
+        let mut db = Database::new();
+        db.set("k".into(), DbValue::from_i64(1));
+        assert!(autosave.tick(&mut db).unwrap());
+
+        db.set("j".into(), DbValue::from_i64(2));
+        assert!(!autosave.tick(&mut db).unwrap());
+        assert_eq!(Database::load(&path).unwrap().count(), 1);
+        assert!(autosave.flush(&mut db).unwrap());
+        assert_eq!(Database::load(&path).unwrap().count(), 2);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn a_save_elsewhere_leaves_changes_pending() {
+        let path = temp_path("autosave-other-save");
+        let other = temp_path("autosave-other-file");
+        let mut autosave = Autosave::new(&path, Duration::from_secs(3600));
+        let mut db = Database::new();
+        db.set("a".into(), DbValue::from_i64(1));
+        assert!(autosave.tick(&mut db).unwrap());
+
+        db.set("b".into(), DbValue::from_i64(2));
+        db.save(&other).unwrap();
+        assert!(!db.is_dirty());
+        assert!(autosave.flush(&mut db).unwrap());
+        assert_eq!(Database::load(&path).unwrap().count(), 2);
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(other);
+    }
+}
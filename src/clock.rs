@@ -0,0 +1,47 @@
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Where a Database gets the time for expiry: the system clock, or a manual
+// one that only moves when told to, so tests don't have to sleep.
+#[derive(Clone, Default)]
+pub struct Clock {
+    // Shared by every clone, so advancing one moves them all.
+    manual: Option<Arc<Mutex<SystemTime>>>,
+}
+
+impl Clock {
+    pub fn manual(start: SystemTime) -> Self {
+        Clock {
+            manual: Some(Arc::new(Mutex::new(start))),
+        }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        match &self.manual {
+            Some(now) => *now.lock().unwrap_or_else(PoisonError::into_inner),
+            None => SystemTime::now(),
+        }
+    }
+
+    // Does nothing to the system clock.
+    pub fn advance(&self, by: Duration) {
+        if let Some(now) = &self.manual {
+            *now.lock().unwrap_or_else(PoisonError::into_inner) += by;
+        }
+    }
+}
+
+// Deadlines are stored and logged as milliseconds since the Unix epoch.
+// Times before it count as the epoch itself.
+
+pub fn to_millis(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+pub fn from_millis(millis: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_millis(millis))
+}
@@ -0,0 +1,3328 @@
+use crate::{
+    DataType, Database, DbValue, TYPE_NAMES, clock, human_size, infer_type, is_type_name,
+    is_valid_key, json, parse_int, to_hex,
+};
+use std::{
+    borrow::Cow,
+    fmt, fs, io, str,
+    time::{Duration, SystemTime},
+};
+
+// (name, usage, description) for every command, shown by HELP.
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("GET", "GET <key>", "Print the value stored at a key"),
+    (
+        "MGET",
+        "MGET <key> [key ...]",
+        "Print several values, (nil) for missing keys",
+    ),
+    (
+        "SET",
+        "SET <key> [type] <value>",
+        "Store a value at a key; the type is inferred if left out",
+    ),
+    (
+        "GETSET",
+        "GETSET <key> [type] <value>",
+        "Store a value and print the one it replaced",
+    ),
+    (
+        "SETNX",
+        "SETNX <key> [type] <value>",
+        "Store a value only if the key is missing; prints 1 or 0",
+    ),
+    (
+        "CAS",
+        "CAS <key> <type> <expected> <new>",
+        "Set a key to new only if it holds expected; prints 1 or 0",
+    ),
+    (
+        "MSET",
+        "MSET <key> <type> <value> ...",
+        "Set several single-token values at once, or none",
+    ),
+    ("DELETE", "DELETE <key>", "Remove a key (alias: DEL)"),
+    (
+        "DELMATCH",
+        "DELMATCH <pattern> [CONFIRM]",
+        "Delete every key matching a glob; * alone needs CONFIRM",
+    ),
+    (
+        "RENAME",
+        "RENAME <old> <new>",
+        "Move a value to a new key, overwriting it",
+    ),
+    (
+        "SWAP",
+        "SWAP <key1> <key2>",
+        "Exchange the values of two existing keys",
+    ),
+    (
+        "COPY",
+        "COPY <src> <dst> [REPLACE]",
+        "Copy a value to another key",
+    ),
+    (
+        "INCR",
+        "INCR <key>",
+        "Add 1 to an int value, starting from 0",
+    ),
+    (
+        "DECR",
+        "DECR <key>",
+        "Subtract 1 from an int value, starting from 0",
+    ),
+    ("INCRBY", "INCRBY <key> <n>", "Add n to an int value"),
+    ("DECRBY", "DECRBY <key> <n>", "Subtract n from an int value"),
+    (
+        "APPEND",
+        "APPEND <key> <text>",
+        "Append text to a string value",
+    ),
+    (
+        "TRUNCATE",
+        "TRUNCATE <key> <n>",
+        "Cut a string to at most n bytes, keeping whole characters",
+    ),
+    (
+        "SETRANGE",
+        "SETRANGE <key> <offset> <text>",
+        "Overwrite a string from offset, padding with spaces past the end",
+    ),
+    (
+        "GETRANGE",
+        "GETRANGE <key> <start> <end>",
+        "Print bytes start to end of a string; negative counts from the end",
+    ),
+    (
+        "LPUSH",
+        "LPUSH <key> [type] <value>",
+        "Add a value to the front of an array (str by default)",
+    ),
+    (
+        "RPUSH",
+        "RPUSH <key> [type] <value>",
+        "Add a value to the end of an array (str by default)",
+    ),
+    (
+        "LRANGE",
+        "LRANGE <key> <start> <stop>",
+        "Print array items start..stop; negative counts from the end",
+    ),
+    (
+        "HSET",
+        "HSET <key> <field> <value>",
+        "Set a field of a hash; prints 1 if the field is new",
+    ),
+    ("HGET", "HGET <key> <field>", "Print one field of a hash"),
+    ("HGETALL", "HGETALL <key>", "Print every field of a hash"),
+    (
+        "STRLEN",
+        "STRLEN <key>",
+        "Print the length of a string in bytes",
+    ),
+    (
+        "STRCOUNT",
+        "STRCOUNT <key>",
+        "Print the length of a string in characters",
+    ),
+    (
+        "DUMPALL",
+        "DUMPALL",
+        "Print commands that rebuild every database, for RUN",
+    ),
+    (
+        "DUMP",
+        "DUMP <key>",
+        "Print a value as a hex blob for RESTORE",
+    ),
+    (
+        "RESTORE",
+        "RESTORE <key> <blob>",
+        "Store a value from a DUMP blob, overwriting the key",
+    ),
+    ("EXISTS", "EXISTS <key>", "Print whether a key exists"),
+    ("TYPE", "TYPE <key>", "Print the type of a value"),
+    ("COUNT", "COUNT", "Print the number of keys (alias: DBSIZE)"),
+    (
+        "CLEAR",
+        "CLEAR CONFIRM",
+        "Remove every key in the selected database",
+    ),
+    (
+        "FLUSHALL",
+        "FLUSHALL CONFIRM",
+        "Remove every key in every database",
+    ),
+    (
+        "EXPIRE",
+        "EXPIRE <key> <seconds>",
+        "Remove a key after some seconds",
+    ),
+    (
+        "PEXPIREAT",
+        "PEXPIREAT <key> <unix-ms> [window-ms]",
+        "Remove a key at a moment; TOUCH restarts the window, if given",
+    ),
+    (
+        "TOUCH",
+        "TOUCH <key>",
+        "Mark a key as used and restart its expiry; prints 1 or 0",
+    ),
+    (
+        "PERSIST",
+        "PERSIST <key>",
+        "Remove a key's expiry; prints 1 if it had one",
+    ),
+    (
+        "TTL",
+        "TTL <key>",
+        "Print seconds left, -1 without expiry, -2 if missing",
+    ),
+    (
+        "MEMORY",
+        "MEMORY",
+        "Print an estimate of the memory used by keys and values",
+    ),
+    ("KEYS", "KEYS", "List every key, sorted"),
+    (
+        "SELECT",
+        "SELECT <n>",
+        "Switch to database n (0-15); each client keeps its own",
+    ),
+    (
+        "MOVE",
+        "MOVE <key> <db>",
+        "Move a key to another database; prints 1 or 0",
+    ),
+    (
+        "SWAPDB",
+        "SWAPDB <a> <b>",
+        "Exchange the contents of two databases",
+    ),
+    (
+        "RANDOMKEY",
+        "RANDOMKEY",
+        "Print a random key, or (nil) if there are none",
+    ),
+    (
+        "SORT",
+        "SORT <pattern> [DESC]",
+        "List int and float values under matching keys, by value",
+    ),
+    (
+        "FINDVAL",
+        "FINDVAL <substring>",
+        "List keys whose string value contains the text",
+    ),
+    (
+        "FINDVALI",
+        "FINDVALI <substring>",
+        "Like FINDVAL, ignoring ASCII case",
+    ),
+    (
+        "RANGE",
+        "RANGE <min> <max>",
+        "List keys with an int value from min to max, by value",
+    ),
+    (
+        "RECENT",
+        "RECENT <n>",
+        "List the last n inserted keys, newest first",
+    ),
+    (
+        "SCAN",
+        "SCAN <pattern>",
+        "List keys matching a glob with * and ?",
+    ),
+    (
+        "KEYSOFTYPE",
+        "KEYSOFTYPE <type>",
+        "List the keys holding values of a type, sorted",
+    ),
+    ("SAVE", "SAVE <path>", "Write the database to a binary file"),
+    (
+        "LOAD",
+        "LOAD <path>",
+        "Replace the database with a saved file",
+    ),
+    ("EXPORT", "EXPORT <path>", "Write the database as JSON"),
+    (
+        "IMPORT",
+        "IMPORT <path>",
+        "Merge entries from a JSON export",
+    ),
+    (
+        "EXPORTCSV",
+        "EXPORTCSV <path>",
+        "Write the database as key,type,value CSV rows",
+    ),
+    (
+        "IMPORTCSV",
+        "IMPORTCSV <path>",
+        "Merge key,type,value rows from a CSV file",
+    ),
+    (
+        "COMPACT",
+        "COMPACT",
+        "Rewrite the WAL from the current state",
+    ),
+    (
+        "DEBUG",
+        "DEBUG [n]",
+        "Dump the first n values (default 100) as hex",
+    ),
+    (
+        "RAW",
+        "RAW <key>",
+        "Print the type tag and a hex dump of a value's bytes",
+    ),
+    (
+        "STATS",
+        "STATS",
+        "Print the number of keys and data bytes per type",
+    ),
+    (
+        "WATCH",
+        "WATCH <key>",
+        "Get a notification line whenever a key is set or deleted",
+    ),
+    (
+        "BEGIN",
+        "BEGIN",
+        "Start a transaction; changes apply only on COMMIT",
+    ),
+    ("COMMIT", "COMMIT", "Keep the changes made since BEGIN"),
+    ("ROLLBACK", "ROLLBACK", "Undo the changes made since BEGIN"),
+    (
+        "RESET",
+        "RESET",
+        "Roll back, drop your watches and select database 0",
+    ),
+    (
+        "RUN",
+        "RUN <path> [--continue-on-error]",
+        "Run the commands in a file, stopping at the first error",
+    ),
+    (
+        "PING",
+        "PING [message]",
+        "Reply PONG, or echo the message, to check the connection",
+    ),
+    ("ECHO", "ECHO <text>", "Print the text back"),
+    (
+        "STATUS",
+        "STATUS",
+        "Print key count, memory, autosave settings and last save",
+    ),
+    ("HELP", "HELP [command]", "List commands, or describe one"),
+    ("HISTORY", "HISTORY", "List the commands entered so far"),
+    ("QUIT", "QUIT", "Close the session (alias: EXIT)"),
+];
+
+// Other spellings of commands in COMMANDS, as (alias, command).
+const ALIASES: &[(&str, &str)] = &[("DEL", "DELETE"), ("DBSIZE", "COUNT"), ("EXIT", "QUIT")];
+
+// Diagnostic commands left out of HELP.
+// TOKENS prints the arguments exactly as the tokenizer split them.
+const HIDDEN: &[&str] = &["TOKENS"];
+
+// Resolves any spelling of a command, in any case, to its name in COMMANDS
+// or HIDDEN. Unknown commands resolve to "".
+pub fn canonical_command(raw: &str) -> &'static str {
+    let alias = ALIASES
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(raw))
+        .map(|(_, command)| *command);
+
+    alias.unwrap_or_else(|| {
+        COMMANDS
+            .iter()
+            .map(|(name, _, _)| name)
+            .chain(HIDDEN)
+            .find(|name| name.eq_ignore_ascii_case(raw))
+            .map_or("", |name| *name)
+    })
+}
+
+fn help(command: Option<&str>) -> CommandResponse {
+    match command {
+        Some(name) => match COMMANDS
+            .iter()
+            .find(|(cmd, _, _)| *cmd == canonical_command(name))
+        {
+            Some((_, usage, description)) => {
+                CommandResponse::Value(format!("Usage: {}\n{}", usage, description))
+            }
+            None => CommandResponse::Error(format!("Unknown command: {}", name)),
+        },
+        None => CommandResponse::Value(
+            COMMANDS
+                .iter()
+                .map(|(_, usage, description)| format!("{:<28} {}", usage, description))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        ),
+    }
+}
+
+// Lines come in as raw bytes from stdin and sockets; anything that isn't
+// UTF-8 is answered with this instead of being dropped.
+pub fn decode_line(bytes: &[u8]) -> Result<&str, CommandResponse> {
+    str::from_utf8(bytes)
+        .map_err(|_| CommandResponse::Error("invalid utf-8 in command".to_string()))
+}
+
+// What a command sends back. Display adds the prefix that tells clients
+// success, failure and plain data apart.
+#[derive(Debug, PartialEq)]
+pub enum CommandResponse {
+    // A change went through; the message is optional.
+    Ok(String),
+    Error(String),
+    // Data the client asked for, shown as-is.
+    Value(String),
+}
+
+impl fmt::Display for CommandResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandResponse::Ok(msg) if msg.is_empty() => write!(f, "OK"),
+            CommandResponse::Ok(msg) => write!(f, "OK {}", msg),
+            CommandResponse::Error(msg) => write!(f, "ERR {}", msg),
+            CommandResponse::Value(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl CommandResponse {
+    // One JSON object per response, for --format json. Values that are
+    // plain integers come out as numbers, everything else as strings.
+    pub fn to_json(&self) -> String {
+        match self {
+            CommandResponse::Ok(msg) if msg.is_empty() => "{\"ok\":true}".to_string(),
+            CommandResponse::Ok(msg) => {
+                format!("{{\"ok\":true,\"message\":\"{}\"}}", json::escape(msg))
+            }
+            CommandResponse::Error(msg) => {
+                format!("{{\"ok\":false,\"error\":\"{}\"}}", json::escape(msg))
+            }
+            CommandResponse::Value(value) => match value.parse::<i64>() {
+                Ok(n) if n.to_string() == *value => format!("{{\"ok\":true,\"value\":{}}}", n),
+                _ => format!("{{\"ok\":true,\"value\":\"{}\"}}", json::escape(value)),
+            },
+        }
+    }
+}
+
+// Splits a command line on any run of whitespace, so extra spaces and tabs
+// never produce empty tokens. Lines starting with `#` are comments and
+// produce none, like blank lines.
+//
+// Double quotes keep whitespace inside a token, `"  a  b "` is one token,
+// and inside them `\"` and `\\` stand for a quote and a backslash. Other
+// backslashes are kept as they are.
+pub fn tokenize(line: &str) -> Result<Vec<String>, CommandResponse> {
+    if line.trim_start().starts_with('#') {
+        return Ok(Vec::new());
+    }
+
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    // Set once the current token has any content, even just `""`.
+    let mut in_token = false;
+    let mut in_quotes = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                in_token = true;
+            }
+            '\\' if in_quotes => match chars.next() {
+                Some(escaped @ ('"' | '\\')) => token.push(escaped),
+                Some(other) => {
+                    token.push('\\');
+                    token.push(other);
+                }
+                None => token.push('\\'),
+            },
+            c if c.is_whitespace() && !in_quotes => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut token));
+                    in_token = false;
+                }
+            }
+            c => {
+                token.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if in_quotes {
+        return Err(CommandResponse::Error("unterminated quote".to_string()));
+    }
+    if in_token {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// A line that tokenizes back to `input`.
+pub fn quote_line(input: &[&str]) -> String {
+    input
+        .iter()
+        .map(|token| quote(token))
+        .collect::<Vec<Cow<str>>>()
+        .join(" ")
+}
+
+// The inverse of tokenize for one token: wraps it in quotes only when it
+// wouldn't survive a plain split. A line break is kept as it is, so callers
+// writing one line per command must not pass one.
+pub fn quote(token: &str) -> Cow<'_, str> {
+    let plain = !token.is_empty()
+        && !token.starts_with('#')
+        && !token
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '"');
+    if plain {
+        return Cow::Borrowed(token);
+    }
+
+    let escaped = token.replace('\\', "\\\\").replace('"', "\\\"");
+    Cow::Owned(format!("\"{}\"", escaped))
+}
+
+// Runs each line of a script, collecting every result. Unless
+// `continue_on_error` is set, the first error ends the script.
+fn run_script(db: &mut Database, path: &str, continue_on_error: bool) -> CommandResponse {
+    let script = match fs::read(path) {
+        Ok(script) => script,
+        Err(e) => return CommandResponse::Error(format!("Failed to read {}: {}", path, e)),
+    };
+
+    let mut output = Vec::new();
+    for (number, line) in script.split(|b| *b == b'\n').enumerate() {
+        let response = match decode_line(line).and_then(tokenize) {
+            Ok(tokens) => {
+                let input = tokens.iter().map(String::as_str).collect::<Vec<&str>>();
+                match input.first() {
+                    None => continue,
+                    Some(command) if canonical_command(command) == "RUN" => {
+                        CommandResponse::Error("RUN cannot be nested".to_string())
+                    }
+                    Some(_) => handle_command(db, &input),
+                }
+            }
+            Err(e) => e,
+        };
+
+        let failed = matches!(response, CommandResponse::Error(_));
+        output.push(response.to_string());
+        if failed && !continue_on_error {
+            output.push(format!("ERR Stopped at line {} of {}", number + 1, path));
+            break;
+        }
+    }
+
+    if output.is_empty() {
+        return CommandResponse::Ok(String::new());
+    }
+    CommandResponse::Value(output.join("\n"))
+}
+
+// How many entries DEBUG shows when not told.
+const DEBUG_LIMIT: usize = 100;
+
+const INVALID_KEY: &str = "Invalid key: keys must not be empty or contain control characters";
+
+// One key per line, or "(empty)".
+fn list_keys(keys: Vec<&String>) -> CommandResponse {
+    if keys.is_empty() {
+        return CommandResponse::Value("(empty)".to_string());
+    }
+    CommandResponse::Value(
+        keys.iter()
+            .map(|key| key.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n"),
+    )
+}
+
+// 16 bytes per line behind their offset, split into groups of four.
+fn hex_dump(data: &[u8]) -> String {
+    if data.is_empty() {
+        return "(no data)".to_string();
+    }
+
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, line)| {
+            let groups = line
+                .chunks(4)
+                .map(to_hex)
+                .collect::<Vec<String>>()
+                .join(" ");
+            format!("{:04x}: {}", i * 16, groups)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Parses `<command> <key> [type] <value>` as taken by SET and friends.
+fn parse_set(db: &Database, input: &[&str]) -> Result<(String, DbValue), String> {
+    // A key and at least one value token are needed.
+    if input.len() < 3 {
+        return Err(format!(
+            "Usage: {} <key> [type] <value>\nTypes: {}",
+            input[0].to_uppercase(),
+            TYPE_NAMES.join(", ")
+        ));
+    };
+
+    if !is_valid_key(input[1]) {
+        return Err(INVALID_KEY.to_string());
+    }
+
+    // A leading type name is always taken as the type. Only null goes
+    // without a value, so `SET k int` is a usage error rather than the
+    // string "int"; `SET k str int` stores that.
+    let value = if input.len() == 3 && input[2].eq_ignore_ascii_case("null") {
+        DbValue::null()
+    } else if input.len() == 3 && is_type_name(input[2]) {
+        return Err(format!(
+            "Usage: {} <key> [type] <value>\n{} needs a value",
+            input[0].to_uppercase(),
+            input[2].to_lowercase()
+        ));
+    } else if input.len() > 3 && is_type_name(input[2]) {
+        let value_str = input[3..].join(" ");
+        DbValue::parse(input[2], &value_str)
+            .map_err(|e| strict_parse_error(db, input[2], &value_str, e))?
+    } else {
+        let value_str = input[2..].join(" ");
+        DbValue::parse(infer_type(&value_str).name(), &value_str)?
+    };
+    db.check_size(&value)?;
+    Ok((input[1].to_string(), value))
+}
+
+// Under --strict-types a value that doesn't fit its type says what it
+// looks like instead, e.g. `3.5` for an int.
+fn strict_parse_error(db: &Database, value_type: &str, value_str: &str, e: String) -> String {
+    if !db.is_strict_types() {
+        return e;
+    }
+    format!(
+        "Type mismatch: '{}' is {}, not {}",
+        value_str,
+        infer_type(value_str).name(),
+        value_type.to_lowercase()
+    )
+}
+
+// Commands that change the data. They are written to the WAL and refused
+// in read-only mode.
+fn is_mutating(command: &str) -> bool {
+    matches!(
+        command,
+        "SET"
+            | "GETSET"
+            | "SETNX"
+            | "CAS"
+            | "MSET"
+            | "DELETE"
+            | "DELMATCH"
+            | "RENAME"
+            | "SWAP"
+            | "MOVE"
+            | "SWAPDB"
+            | "COPY"
+            | "INCR"
+            | "DECR"
+            | "INCRBY"
+            | "DECRBY"
+            | "APPEND"
+            | "TRUNCATE"
+            | "SETRANGE"
+            | "LPUSH"
+            | "RPUSH"
+            | "HSET"
+            | "RESTORE"
+            | "CLEAR"
+            | "FLUSHALL"
+            | "EXPIRE"
+            | "PEXPIREAT"
+            | "TOUCH"
+            | "PERSIST"
+            | "LOAD"
+            | "IMPORT"
+            | "IMPORTCSV"
+    )
+}
+
+// Runs one command line and returns what should be shown to the client.
+pub fn handle_command(db: &mut Database, input: &[&str]) -> CommandResponse {
+    let Some(raw) = input.first() else {
+        return CommandResponse::Ok(String::new());
+    };
+    let command = canonical_command(raw);
+
+    if is_mutating(command) && db.is_readonly() {
+        return CommandResponse::Error("database is read-only".to_string());
+    }
+    if is_mutating(command)
+        && let Err(e) = db.check_transaction_lock()
+    {
+        return CommandResponse::Error(e);
+    }
+
+    let response = run_command(db, command, input);
+
+    // Only commands that succeeded are logged, so a replay never repeats a
+    // rejected one. LOAD and the imports read outside files, so the log is
+    // compacted after them instead of recording the command.
+    if is_mutating(command)
+        && !matches!(command, "LOAD" | "IMPORT" | "IMPORTCSV")
+        && !matches!(response, CommandResponse::Error(_))
+        && let Err(e) = db.append_wal(&wal_line(db, command, input))
+    {
+        return CommandResponse::Error(format!("Applied, but failed to write WAL: {}", e));
+    }
+    response
+}
+
+// What the WAL records for `input`. Expiries are logged as the moment they
+// end, so replaying the log later doesn't restart them.
+fn wal_line(db: &Database, command: &str, input: &[&str]) -> String {
+    let window = match (command, input) {
+        ("EXPIRE", [_, _, seconds, ..]) => seconds.parse().ok().map(Duration::from_secs),
+        ("TOUCH", [_, key, ..]) => db.expiry_window(key),
+        _ => None,
+    };
+    match window.and_then(|window| Some((db.now().checked_add(window)?, window))) {
+        Some((at, window)) => expire_line(input[1], at, Some(window)),
+        None => quote_line(input),
+    }
+}
+
+// PEXPIREAT for a key that ends at `at`.
+pub(crate) fn expire_line(key: &str, at: SystemTime, window: Option<Duration>) -> String {
+    let mut line = format!("PEXPIREAT {} {}", quote(key), clock::to_millis(at));
+    if let Some(window) = window {
+        line.push_str(&format!(" {}", window.as_millis()));
+    }
+    line
+}
+
+fn run_command(db: &mut Database, command: &str, input: &[&str]) -> CommandResponse {
+    match command {
+        // <== INPUT[0] = COMMAND
+        "GET" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: GET <key>".to_string());
+            }
+
+            let key = db.normalize_key(input[1]).into_owned();
+            match db.get(&key).map(DbValue::to_display_string) {
+                Some(Ok(value)) => CommandResponse::Value(format!("{}: {}", key, value)),
+                Some(Err(e)) => CommandResponse::Error(format!("Corrupt value at {}: {}", key, e)),
+                None => CommandResponse::Error("Key not found".to_string()),
+            }
+        }
+        "MGET" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: MGET <key> [key ...]".to_string());
+            }
+
+            let lines = input[1..]
+                .iter()
+                .zip(db.mget(&input[1..]))
+                .map(|(key, value)| {
+                    let key = db.normalize_key(key);
+                    match value {
+                        Some(value) => format!("{}: {}", key, value),
+                        None => format!("{}: (nil)", key),
+                    }
+                })
+                .collect::<Vec<String>>();
+            CommandResponse::Value(lines.join("\n"))
+        }
+        "SET" => {
+            let (key, value) = match parse_set(db, input) {
+                Ok(parsed) => parsed,
+                Err(e) => return CommandResponse::Error(e),
+            };
+            if let Err(e) = db.check_type(&key, &value) {
+                return CommandResponse::Error(e);
+            }
+            db.set(key, value);
+            CommandResponse::Ok(String::new())
+        }
+        "GETSET" => {
+            let (key, value) = match parse_set(db, input) {
+                Ok(parsed) => parsed,
+                Err(e) => return CommandResponse::Error(e),
+            };
+            if let Err(e) = db.check_type(&key, &value) {
+                return CommandResponse::Error(e);
+            }
+            match db.getset(key, value) {
+                Some(old) => CommandResponse::Value(old.to_string()),
+                None => CommandResponse::Value("(nil)".to_string()),
+            }
+        }
+        "SETNX" => {
+            let (key, value) = match parse_set(db, input) {
+                Ok(parsed) => parsed,
+                Err(e) => return CommandResponse::Error(e),
+            };
+            CommandResponse::Value(if db.set_nx(key, value) { "1" } else { "0" }.to_string())
+        }
+        "CAS" => {
+            if input.len() < 5 {
+                return CommandResponse::Error(
+                    "Usage: CAS <key> <type> <expected> <new>".to_string(),
+                );
+            }
+            if !is_valid_key(input[1]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            // Both sides are parsed, so `01` and `1` are the same int.
+            let values = DbValue::parse(input[2], input[3]).and_then(|expected| {
+                let new = DbValue::parse(input[2], &input[4..].join(" "))?;
+                db.check_size(&new)?;
+                Ok((expected, new))
+            });
+            match values {
+                Ok((expected, new)) => CommandResponse::Value(
+                    if db.compare_and_set(input[1], &expected, new) {
+                        "1"
+                    } else {
+                        "0"
+                    }
+                    .to_string(),
+                ),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "MSET" => {
+            // Every value is a single token here; quote strings with spaces.
+            let triples = &input[1..];
+            if triples.is_empty() {
+                return CommandResponse::Error(
+                    "Usage: MSET <key> <type> <value> [<key> <type> <value> ...]".to_string(),
+                );
+            }
+            if !triples.len().is_multiple_of(3) {
+                let rest = &triples[triples.len() - triples.len() % 3..];
+                return CommandResponse::Error(format!(
+                    "Triple {} ({}) is incomplete: expected <key> <type> <value>",
+                    triples.len() / 3 + 1,
+                    rest.join(" ")
+                ));
+            }
+
+            // Parse the whole batch first so a bad triple changes nothing.
+            let mut values = Vec::new();
+            for (i, triple) in triples.chunks(3).enumerate() {
+                if !is_valid_key(triple[0]) {
+                    return CommandResponse::Error(format!(
+                        "Triple {} ({}) is invalid: {}",
+                        i + 1,
+                        triple.join(" "),
+                        INVALID_KEY
+                    ));
+                }
+                let value = DbValue::parse(triple[1], triple[2])
+                    .map_err(|e| strict_parse_error(db, triple[1], triple[2], e))
+                    .and_then(|value| db.check_size(&value).map(|_| value))
+                    .and_then(|value| db.check_type(triple[0], &value).map(|_| value));
+                match value {
+                    Ok(value) => values.push((triple[0].to_string(), value)),
+                    Err(e) => {
+                        return CommandResponse::Error(format!(
+                            "Triple {} ({}) is invalid: {}",
+                            i + 1,
+                            triple.join(" "),
+                            e
+                        ));
+                    }
+                }
+            }
+
+            let count = values.len();
+            for (key, value) in values {
+                db.set(key, value);
+            }
+            CommandResponse::Ok(format!("{} keys set", count))
+        }
+        "DELETE" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: DELETE <key>".to_string());
+            }
+
+            match db.delete(input[1]) {
+                Some(_) => CommandResponse::Ok(format!("Deleted {}", db.normalize_key(input[1]))),
+                None => CommandResponse::Error("Key not found".to_string()),
+            }
+        }
+        "DELMATCH" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: DELMATCH <pattern> [CONFIRM]".to_string());
+            }
+            // A pattern of only `*` matches everything, so it needs the same
+            // guard as CLEAR.
+            if input[1].chars().all(|c| c == '*')
+                && !input
+                    .get(2)
+                    .is_some_and(|flag| flag.eq_ignore_ascii_case("CONFIRM"))
+            {
+                return CommandResponse::Error(format!(
+                    "This removes every key. Use: DELMATCH {} CONFIRM",
+                    input[1]
+                ));
+            }
+
+            let removed = db.delete_matching(input[1]);
+            CommandResponse::Ok(format!("Removed {} keys", removed))
+        }
+        "RENAME" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: RENAME <old> <new>".to_string());
+            }
+            if !is_valid_key(input[2]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            let old = db.normalize_key(input[1]).into_owned();
+            let new = db.normalize_key(input[2]).into_owned();
+            let overwrites = old != new && db.exists(&new);
+            match db.rename(&old, &new) {
+                Ok(()) if overwrites => CommandResponse::Ok(format!(
+                    "Renamed {} to {} (overwrote existing value)",
+                    old, new
+                )),
+                Ok(()) => CommandResponse::Ok(format!("Renamed {} to {}", old, new)),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "SWAP" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: SWAP <key1> <key2>".to_string());
+            }
+
+            match db.swap(input[1], input[2]) {
+                Ok(()) => CommandResponse::Ok(format!(
+                    "Swapped {} and {}",
+                    db.normalize_key(input[1]),
+                    db.normalize_key(input[2])
+                )),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "COPY" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: COPY <src> <dst> [REPLACE]".to_string());
+            }
+            if !is_valid_key(input[2]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            let replace = input
+                .get(3)
+                .is_some_and(|flag| flag.eq_ignore_ascii_case("REPLACE"));
+            match db.copy(input[1], input[2], replace) {
+                Ok(()) => CommandResponse::Ok(format!(
+                    "Copied {} to {}",
+                    db.normalize_key(input[1]),
+                    db.normalize_key(input[2])
+                )),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "INCR" | "DECR" => {
+            if input.len() < 2 {
+                return CommandResponse::Error(format!("Usage: {} <key>", input[0].to_uppercase()));
+            }
+            if !is_valid_key(input[1]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            let delta = if command == "INCR" { 1 } else { -1 };
+            match db.adjust_int(input[1], delta) {
+                Ok(new) => CommandResponse::Value(new.to_string()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "INCRBY" | "DECRBY" => {
+            if input.len() < 3 {
+                return CommandResponse::Error(format!(
+                    "Usage: {} <key> <n>",
+                    input[0].to_uppercase()
+                ));
+            }
+            if !is_valid_key(input[1]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            let delta = match parse_int(input[2]) {
+                Ok(n) if command == "INCRBY" => n,
+                Ok(n) => match n.checked_neg() {
+                    Some(delta) => delta,
+                    None => {
+                        return CommandResponse::Error(
+                            "Result would overflow an integer".to_string(),
+                        );
+                    }
+                },
+                Err(e) => return CommandResponse::Error(e),
+            };
+
+            match db.adjust_int(input[1], delta) {
+                Ok(new) => CommandResponse::Value(new.to_string()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "APPEND" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: APPEND <key> <text>".to_string());
+            }
+            if !is_valid_key(input[1]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            match db.append(input[1], &input[2..].join(" ")) {
+                Ok(len) => CommandResponse::Value(len.to_string()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "TRUNCATE" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: TRUNCATE <key> <n>".to_string());
+            }
+
+            let len = match input[2].parse::<usize>() {
+                Ok(len) => len,
+                Err(_) => return CommandResponse::Error(format!("Invalid length: {}", input[2])),
+            };
+            match db.truncate(input[1], len) {
+                Ok(len) => CommandResponse::Value(len.to_string()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "SETRANGE" => {
+            if input.len() < 4 {
+                return CommandResponse::Error("Usage: SETRANGE <key> <offset> <text>".to_string());
+            }
+            if !is_valid_key(input[1]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            let offset = match input[2].parse::<usize>() {
+                Ok(offset) => offset,
+                Err(_) => return CommandResponse::Error(format!("Invalid offset: {}", input[2])),
+            };
+            match db.setrange(input[1], offset, &input[3..].join(" ")) {
+                Ok(len) => CommandResponse::Value(len.to_string()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "GETRANGE" => {
+            if input.len() < 4 {
+                return CommandResponse::Error("Usage: GETRANGE <key> <start> <end>".to_string());
+            }
+
+            let (start, end) = match (parse_int(input[2]), parse_int(input[3])) {
+                (Ok(start), Ok(end)) => (start, end),
+                (Err(e), _) | (_, Err(e)) => return CommandResponse::Error(e),
+            };
+            match db.getrange(input[1], start, end) {
+                Ok(text) => CommandResponse::Value(text),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "LPUSH" | "RPUSH" => {
+            if input.len() < 3 {
+                return CommandResponse::Error(format!("Usage: {} <key> [type] <value>", command));
+            }
+            if !is_valid_key(input[1]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            // A leading type name only counts as one when a value follows it.
+            let item = if input.len() > 3 && is_type_name(input[2]) {
+                DbValue::parse(input[2], &input[3..].join(" "))
+            } else {
+                Ok(DbValue::from_str(&input[2..].join(" ")))
+            };
+            let item = match item {
+                Ok(item) => item,
+                Err(e) => return CommandResponse::Error(e),
+            };
+
+            match db.push(input[1], item, command == "LPUSH") {
+                Ok(len) => CommandResponse::Value(len.to_string()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "LRANGE" => {
+            if input.len() < 4 {
+                return CommandResponse::Error("Usage: LRANGE <key> <start> <stop>".to_string());
+            }
+
+            let (start, stop) = match (parse_int(input[2]), parse_int(input[3])) {
+                (Ok(start), Ok(stop)) => (start, stop),
+                (Err(e), _) | (_, Err(e)) => return CommandResponse::Error(e),
+            };
+            match db.lrange(input[1], start, stop) {
+                Ok(items) if items.is_empty() => CommandResponse::Value("(empty)".to_string()),
+                Ok(items) => CommandResponse::Value(
+                    items
+                        .iter()
+                        .map(DbValue::to_string)
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                ),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "HSET" => {
+            if input.len() < 4 {
+                return CommandResponse::Error("Usage: HSET <key> <field> <value>".to_string());
+            }
+            if !is_valid_key(input[1]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            match db.hset(input[1], input[2], &input[3..].join(" ")) {
+                Ok(added) => CommandResponse::Value(if added { "1" } else { "0" }.to_string()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "HGET" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: HGET <key> <field>".to_string());
+            }
+
+            match db.hget(input[1], input[2]) {
+                Ok(value) => CommandResponse::Value(value),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "HGETALL" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: HGETALL <key>".to_string());
+            }
+
+            match db.hgetall(input[1]) {
+                Ok(fields) if fields.is_empty() => CommandResponse::Value("(empty)".to_string()),
+                Ok(fields) => CommandResponse::Value(
+                    fields
+                        .iter()
+                        .map(|(field, value)| format!("{}: {}", field, value))
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                ),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "STRLEN" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: STRLEN <key>".to_string());
+            }
+
+            match db.get(input[1]) {
+                Some(value) if value.typetag == DataType::String => {
+                    CommandResponse::Value(value.data.len().to_string())
+                }
+                Some(_) => CommandResponse::Error("Value is not a string".to_string()),
+                None => CommandResponse::Error("Key not found".to_string()),
+            }
+        }
+        "STRCOUNT" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: STRCOUNT <key>".to_string());
+            }
+
+            match db.get(input[1]) {
+                Some(value) if value.typetag == DataType::String => match value.as_string() {
+                    Some(text) => CommandResponse::Value(text.chars().count().to_string()),
+                    None => CommandResponse::Error("Value is not valid UTF-8".to_string()),
+                },
+                Some(_) => CommandResponse::Error("Value is not a string".to_string()),
+                None => CommandResponse::Error("Key not found".to_string()),
+            }
+        }
+        "DUMP" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: DUMP <key>".to_string());
+            }
+
+            match db.get(input[1]) {
+                Some(value) => CommandResponse::Value(value.dump()),
+                None => CommandResponse::Error("Key not found".to_string()),
+            }
+        }
+        "RAW" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: RAW <key>".to_string());
+            }
+
+            match db.get(input[1]) {
+                Some(value) => CommandResponse::Value(format!(
+                    "{} (tag {}), {} bytes\n{}",
+                    value.type_name(),
+                    value.typetag.clone() as u8,
+                    value.data.len(),
+                    hex_dump(&value.data)
+                )),
+                None => CommandResponse::Error("Key not found".to_string()),
+            }
+        }
+        "RESTORE" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: RESTORE <key> <blob>".to_string());
+            }
+            if !is_valid_key(input[1]) {
+                return CommandResponse::Error(INVALID_KEY.to_string());
+            }
+
+            let value = DbValue::restore(input[2])
+                .and_then(|value| db.check_size(&value).map(|_| value))
+                .and_then(|value| db.check_type(input[1], &value).map(|_| value));
+            match value {
+                Ok(value) => {
+                    db.set(input[1].to_string(), value);
+                    CommandResponse::Ok(String::new())
+                }
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "EXISTS" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: EXISTS <key>".to_string());
+            }
+
+            CommandResponse::Value(db.exists(input[1]).to_string())
+        }
+        "TYPE" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: TYPE <key>".to_string());
+            }
+
+            match db.get(input[1]) {
+                Some(value) => CommandResponse::Value(value.type_name().to_string()),
+                None => CommandResponse::Error("Key not found".to_string()),
+            }
+        }
+        "COUNT" => CommandResponse::Value(db.count().to_string()),
+        "CLEAR" | "FLUSHALL" => {
+            // Guard against wiping everything with a stray CLEAR.
+            if input.len() < 2 || !input[1].eq_ignore_ascii_case("CONFIRM") {
+                return CommandResponse::Error(format!(
+                    "This removes every key{}. Use: {} CONFIRM",
+                    if command == "FLUSHALL" {
+                        " in every database"
+                    } else {
+                        ""
+                    },
+                    command
+                ));
+            }
+
+            let removed = if command == "FLUSHALL" {
+                db.clear_all()
+            } else {
+                let removed = db.count();
+                db.clear();
+                removed
+            };
+            CommandResponse::Ok(format!("Removed {} keys", removed))
+        }
+        "EXPIRE" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: EXPIRE <key> <seconds>".to_string());
+            }
+
+            let seconds = match input[2].parse::<u64>() {
+                Ok(seconds) => seconds,
+                Err(_) => {
+                    return CommandResponse::Error(format!(
+                        "Invalid number of seconds: {}",
+                        input[2]
+                    ));
+                }
+            };
+
+            match db.expire(input[1], Duration::from_secs(seconds)) {
+                Ok(true) => CommandResponse::Ok(format!(
+                    "{} expires in {} seconds",
+                    db.normalize_key(input[1]),
+                    seconds
+                )),
+                Ok(false) => CommandResponse::Error("Key not found".to_string()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "PEXPIREAT" => {
+            if input.len() < 3 {
+                return CommandResponse::Error(
+                    "Usage: PEXPIREAT <key> <unix-ms> [window-ms]".to_string(),
+                );
+            }
+
+            let Some(at) = input[2].parse().ok().and_then(clock::from_millis) else {
+                return CommandResponse::Error(format!("Invalid time: {}", input[2]));
+            };
+            let window = match input.get(3).map(|ms| ms.parse().map(Duration::from_millis)) {
+                None => None,
+                Some(Ok(window)) => Some(window),
+                Some(Err(_)) => {
+                    return CommandResponse::Error(format!("Invalid window: {}", input[3]));
+                }
+            };
+
+            if db.expire_at(input[1], at, window) {
+                CommandResponse::Ok(String::new())
+            } else {
+                CommandResponse::Error("Key not found".to_string())
+            }
+        }
+        "TOUCH" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: TOUCH <key>".to_string());
+            }
+
+            CommandResponse::Value(if db.touch(input[1]) { "1" } else { "0" }.to_string())
+        }
+        "PERSIST" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: PERSIST <key>".to_string());
+            }
+
+            CommandResponse::Value(if db.persist(input[1]) { "1" } else { "0" }.to_string())
+        }
+        "TTL" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: TTL <key>".to_string());
+            }
+
+            CommandResponse::Value(db.ttl(input[1]).to_string())
+        }
+        "MEMORY" => {
+            let bytes = db.estimated_size();
+            CommandResponse::Value(format!(
+                "{} ({} bytes, {} keys)",
+                human_size(bytes),
+                bytes,
+                db.count()
+            ))
+        }
+        "KEYS" => list_keys(db.keys()),
+        "SELECT" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: SELECT <n>".to_string());
+            }
+
+            let index = match input[1].parse::<usize>() {
+                Ok(index) => index,
+                Err(_) => return CommandResponse::Error(format!("Invalid database: {}", input[1])),
+            };
+            match db.select(index) {
+                Ok(()) => CommandResponse::Ok(String::new()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "MOVE" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: MOVE <key> <db>".to_string());
+            }
+
+            let index = match input[2].parse::<usize>() {
+                Ok(index) => index,
+                Err(_) => return CommandResponse::Error(format!("Invalid database: {}", input[2])),
+            };
+            match db.move_key(input[1], index) {
+                Ok(moved) => CommandResponse::Value(if moved { "1" } else { "0" }.to_string()),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "SWAPDB" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: SWAPDB <a> <b>".to_string());
+            }
+
+            let (a, b) = match (input[1].parse::<usize>(), input[2].parse::<usize>()) {
+                (Ok(a), Ok(b)) => (a, b),
+                (Err(_), _) => {
+                    return CommandResponse::Error(format!("Invalid database: {}", input[1]));
+                }
+                (_, Err(_)) => {
+                    return CommandResponse::Error(format!("Invalid database: {}", input[2]));
+                }
+            };
+            match db.swap_databases(a, b) {
+                Ok(()) => CommandResponse::Ok(format!("Swapped databases {} and {}", a, b)),
+                Err(e) => CommandResponse::Error(e),
+            }
+        }
+        "RANDOMKEY" => {
+            CommandResponse::Value(db.random_key().unwrap_or_else(|| "(nil)".to_string()))
+        }
+        "RECENT" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: RECENT <n>".to_string());
+            }
+
+            let n = match input[1].parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => return CommandResponse::Error(format!("Invalid count: {}", input[1])),
+            };
+
+            list_keys(db.recent(n))
+        }
+        "SCAN" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: SCAN <pattern>".to_string());
+            }
+
+            list_keys(db.scan(input[1]))
+        }
+        "KEYSOFTYPE" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: KEYSOFTYPE <type>".to_string());
+            }
+
+            match DataType::from_name(input[1]) {
+                Some(value_type) => list_keys(db.keys_of_type(&value_type)),
+                None => CommandResponse::Error(format!(
+                    "Unknown type: {}. Use: {}, array, hash",
+                    input[1],
+                    TYPE_NAMES.join(", ")
+                )),
+            }
+        }
+        "SORT" => {
+            let descending = input
+                .get(2)
+                .is_some_and(|arg| arg.eq_ignore_ascii_case("DESC"));
+            if input.len() < 2 || (input.len() > 2 && !descending) {
+                return CommandResponse::Error("Usage: SORT <pattern> [DESC]".to_string());
+            }
+
+            let entries = db.sort_numeric(input[1], descending);
+            if entries.is_empty() {
+                return CommandResponse::Value("(empty)".to_string());
+            }
+            CommandResponse::Value(
+                entries
+                    .iter()
+                    .map(|(key, value)| format!("{} {}", key, value))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            )
+        }
+        "FINDVAL" | "FINDVALI" => {
+            if input.len() < 2 {
+                return CommandResponse::Error(format!("Usage: {} <substring>", command));
+            }
+
+            list_keys(db.find_value(&input[1..].join(" "), command == "FINDVALI"))
+        }
+        "RANGE" => {
+            if input.len() < 3 {
+                return CommandResponse::Error("Usage: RANGE <min> <max>".to_string());
+            }
+
+            let (min, max) = match (parse_int(input[1]), parse_int(input[2])) {
+                (Ok(min), Ok(max)) => (min, max),
+                (Err(e), _) | (_, Err(e)) => return CommandResponse::Error(e),
+            };
+            list_keys(db.range(min, max))
+        }
+        "SAVE" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: SAVE <path>".to_string());
+            }
+
+            match db.save(input[1]) {
+                Ok(()) => {
+                    CommandResponse::Ok(format!("Saved {} keys to {}", db.total_count(), input[1]))
+                }
+                Err(e) => CommandResponse::Error(format!("Failed to save: {}", e)),
+            }
+        }
+        "LOAD" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: LOAD <path>".to_string());
+            }
+
+            match Database::load(input[1]) {
+                Ok(loaded) => {
+                    let skipped = db.replace(loaded);
+                    if let Err(e) = db.compact_wal() {
+                        return CommandResponse::Error(format!(
+                            "Loaded, but failed to compact WAL: {}",
+                            e
+                        ));
+                    }
+                    let mut lines = vec![format!(
+                        "Loaded {} keys from {}",
+                        db.total_count(),
+                        input[1]
+                    )];
+                    lines.extend(
+                        skipped
+                            .iter()
+                            .map(|warning| format!("Warning: skipped {}", warning)),
+                    );
+                    CommandResponse::Ok(lines.join("\n"))
+                }
+                // A file from a newer build; say so plainly.
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    CommandResponse::Error(e.to_string())
+                }
+                Err(e) => CommandResponse::Error(format!("Failed to load: {}", e)),
+            }
+        }
+        "RUN" => {
+            let continue_on_error = input.get(2) == Some(&"--continue-on-error");
+            if input.len() < 2 || (input.len() > 2 && !continue_on_error) {
+                return CommandResponse::Error(
+                    "Usage: RUN <path> [--continue-on-error]".to_string(),
+                );
+            }
+
+            run_script(db, input[1], continue_on_error)
+        }
+        "EXPORT" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: EXPORT <path>".to_string());
+            }
+
+            match std::fs::write(input[1], db.export_json()) {
+                Ok(()) => {
+                    CommandResponse::Ok(format!("Exported {} keys to {}", db.count(), input[1]))
+                }
+                Err(e) => CommandResponse::Error(format!("Failed to export: {}", e)),
+            }
+        }
+        "EXPORTCSV" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: EXPORTCSV <path>".to_string());
+            }
+
+            let (text, skipped) = db.export_csv();
+            if let Err(e) = std::fs::write(input[1], text) {
+                return CommandResponse::Error(format!("Failed to export: {}", e));
+            }
+            let mut lines = vec![format!(
+                "Exported {} keys to {}, skipped {}",
+                db.count() - skipped.len(),
+                input[1],
+                skipped.len()
+            )];
+            lines.extend(
+                skipped
+                    .iter()
+                    .map(|warning| format!("Warning: skipped {}", warning)),
+            );
+            CommandResponse::Ok(lines.join("\n"))
+        }
+        "IMPORT" | "IMPORTCSV" => {
+            if input.len() < 2 {
+                return CommandResponse::Error(format!("Usage: {} <path>", command));
+            }
+
+            let text = match std::fs::read_to_string(input[1]) {
+                Ok(text) => text,
+                Err(e) => return CommandResponse::Error(format!("Failed to import: {}", e)),
+            };
+
+            let result = if command == "IMPORT" {
+                db.import_json(&text)
+            } else {
+                db.import_csv(&text)
+            };
+            match result {
+                Ok((imported, skipped)) => {
+                    if let Err(e) = db.compact_wal() {
+                        return CommandResponse::Error(format!(
+                            "Imported, but failed to compact WAL: {}",
+                            e
+                        ));
+                    }
+                    let mut lines = vec![format!(
+                        "Imported {} keys, skipped {}",
+                        imported,
+                        skipped.len()
+                    )];
+                    lines.extend(
+                        skipped
+                            .iter()
+                            .map(|warning| format!("Warning: skipped {}", warning)),
+                    );
+                    CommandResponse::Ok(lines.join("\n"))
+                }
+                Err(e) => CommandResponse::Error(format!("Failed to import: {}", e)),
+            }
+        }
+        "DEBUG" => {
+            let limit = match input.get(1).map(|n| n.parse::<usize>()) {
+                None => DEBUG_LIMIT,
+                Some(Ok(n)) => n,
+                Some(Err(_)) => {
+                    return CommandResponse::Error(format!("Invalid count: {}", input[1]));
+                }
+            };
+
+            // Sorted by key so the output can be diffed.
+            let mut entries = db.iter().collect::<Vec<_>>();
+            entries.sort_by_key(|(key, _value)| *key);
+            let more = entries.len().saturating_sub(limit);
+            let mut lines = entries
+                .into_iter()
+                .take(limit)
+                .map(|(key, value)| {
+                    let mut hex_string = format!("{:02x}", value.typetag.clone() as u8);
+                    hex_string.push_str(&to_hex(&value.data));
+                    format!(
+                        "{} ({}): {}  hex: {}",
+                        key,
+                        value.type_name(),
+                        value,
+                        hex_string
+                    )
+                })
+                .collect::<Vec<String>>();
+            if more > 0 {
+                lines.push(format!("... {} more", more));
+            }
+            CommandResponse::Value(lines.join("\n"))
+        }
+        "STATS" => {
+            let stats = db.stats();
+            if stats.is_empty() {
+                return CommandResponse::Value("(empty)".to_string());
+            }
+
+            let mut stats = stats.into_iter().collect::<Vec<_>>();
+            stats.sort();
+            let lines = stats
+                .iter()
+                .map(|(name, (count, bytes))| format!("{}: {} keys, {} bytes", name, count, bytes))
+                .collect::<Vec<String>>();
+            CommandResponse::Value(lines.join("\n"))
+        }
+        "DUMPALL" => {
+            let script = db.dump_script();
+            // A comment, so the output still runs as a script.
+            if script.is_empty() {
+                return CommandResponse::Value("# (empty)".to_string());
+            }
+            CommandResponse::Value(script.trim_end().to_string())
+        }
+        "COMPACT" => {
+            if !db.wal_enabled() {
+                return CommandResponse::Error(
+                    "WAL is not enabled (start with --wal <path>)".to_string(),
+                );
+            }
+
+            match db.compact_wal() {
+                Ok(()) => {
+                    CommandResponse::Ok(format!("Compacted WAL to {} keys", db.total_count()))
+                }
+                Err(e) => CommandResponse::Error(format!("Failed to compact WAL: {}", e)),
+            }
+        }
+        "WATCH" => {
+            if input.len() < 2 {
+                return CommandResponse::Error("Usage: WATCH <key>".to_string());
+            }
+
+            if db.watch(input[1]) {
+                CommandResponse::Ok(format!("Watching {}", db.normalize_key(input[1])))
+            } else {
+                CommandResponse::Ok(format!("Already watching {}", db.normalize_key(input[1])))
+            }
+        }
+        "BEGIN" => match db.begin() {
+            Ok(()) => CommandResponse::Ok("Transaction started".to_string()),
+            Err(e) => CommandResponse::Error(e),
+        },
+        "COMMIT" => match db.commit() {
+            Ok(()) => CommandResponse::Ok("Transaction committed".to_string()),
+            Err(e) => CommandResponse::Error(e),
+        },
+        "ROLLBACK" => match db.rollback() {
+            Ok(()) => CommandResponse::Ok("Transaction rolled back".to_string()),
+            Err(e) => CommandResponse::Error(e),
+        },
+        "RESET" => {
+            db.reset_session();
+            CommandResponse::Ok("Session reset".to_string())
+        }
+        "PING" => match input.get(1..) {
+            Some(message) if !message.is_empty() => CommandResponse::Value(message.join(" ")),
+            _ => CommandResponse::Value("PONG".to_string()),
+        },
+        "ECHO" => CommandResponse::Value(input[1..].join(" ")),
+        "TOKENS" => CommandResponse::Value(
+            input[1..]
+                .iter()
+                .enumerate()
+                .map(|(i, token)| format!("{}: {:?}", i + 1, token))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        ),
+        "HELP" => help(input.get(1).copied()),
+        _ => CommandResponse::Error("Unknown command".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_path;
+
+    fn run(db: &mut Database, line: &str) -> CommandResponse {
+        let tokens = tokenize(line).unwrap();
+        let input = tokens.iter().map(String::as_str).collect::<Vec<&str>>();
+        handle_command(db, &input)
+    }
+
+    fn is_error(response: &CommandResponse) -> bool {
+        matches!(response, CommandResponse::Error(_))
+    }
+
+    fn value(text: &str) -> CommandResponse {
+        CommandResponse::Value(text.to_string())
+    }
+
+    fn error(text: &str) -> CommandResponse {
+        CommandResponse::Error(text.to_string())
+    }
+
+    #[test]
+    fn set_infers_the_type_when_left_out() {
+        let mut db = Database::new();
+        for (value, expected) in [
+            ("42", "int"),
+            ("3.14", "float"),
+            ("true", "bool"),
+            ("hello", "str"),
+            ("007", "int"),
+        ] {
+            run(&mut db, &format!("SET k {}", value));
+            assert_eq!(
+                run(&mut db, "TYPE k"),
+                CommandResponse::Value(expected.into())
+            );
+        }
+    }
+
+    #[test]
+    fn set_takes_an_explicit_type_over_inference() {
+        let mut db = Database::new();
+        run(&mut db, "SET k str 007");
+        assert_eq!(
+            run(&mut db, "GET k"),
+            CommandResponse::Value("k: 007".into())
+        );
+        run(&mut db, "SET k float 1");
+        assert_eq!(
+            run(&mut db, "TYPE k"),
+            CommandResponse::Value("float".into())
+        );
+    }
+
+    #[test]
+    fn set_with_a_bare_type_name_is_a_usage_error() {
+        let mut db = Database::new();
+        for line in ["SET k int", "SET k str", "SET k Float"] {
+            assert!(is_error(&run(&mut db, line)), "{}", line);
+        }
+        assert!(!db.exists("k"));
+
+        // null is the one type that takes no value.
+        assert_eq!(
+            run(&mut db, "SET k null"),
+            CommandResponse::Ok(String::new())
+        );
+        assert_eq!(
+            run(&mut db, "TYPE k"),
+            CommandResponse::Value("null".into())
+        );
+    }
+
+    #[test]
+    fn save_and_load_count_keys_in_every_database() {
+        let path = temp_path("save-count");
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        run(&mut db, "SET b 2");
+        run(&mut db, "SELECT 3");
+        run(&mut db, "SET c 3");
+
+        let saved = run(&mut db, &format!("SAVE {}", path));
+        assert_eq!(
+            saved,
+            CommandResponse::Ok(format!("Saved 3 keys to {}", path))
+        );
+        let loaded = run(&mut db, &format!("LOAD {}", path));
+        assert_eq!(
+            loaded,
+            CommandResponse::Ok(format!("Loaded 3 keys from {}", path))
+        );
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn strict_types_cover_every_overwrite() {
+        let mut db = Database::new();
+        db.set_strict_types(true);
+        run(&mut db, "SET n int 1");
+        run(&mut db, "SET s str text");
+
+        // A str blob restored over an int.
+        let restore = format!("RESTORE n {}", DbValue::from_str("a").dump());
+        assert!(is_error(&run(&mut db, &restore)));
+        assert!(is_error(&run(&mut db, "RENAME s n")));
+        assert!(is_error(&run(&mut db, "COPY s n REPLACE")));
+        assert!(is_error(&run(&mut db, "SWAP s n")));
+        assert_eq!(run(&mut db, "TYPE n"), CommandResponse::Value("int".into()));
+        assert_eq!(run(&mut db, "TYPE s"), CommandResponse::Value("str".into()));
+
+        // Same types, and new keys, are still fine.
+        run(&mut db, "SET m int 2");
+        assert!(!is_error(&run(&mut db, "RENAME m n")));
+        assert!(!is_error(&run(&mut db, "COPY s t")));
+        let restore = format!("RESTORE n {}", DbValue::from_i64(5).dump());
+        assert!(!is_error(&run(&mut db, &restore)));
+    }
+
+    #[test]
+    fn quotes_keep_whitespace_and_escapes() {
+        assert_eq!(
+            tokenize(r#"SET k str "  hello  world  ""#).unwrap(),
+            vec!["SET", "k", "str", "  hello  world  "]
+        );
+        assert_eq!(
+            tokenize(r#"ECHO "say \"hi\" \\" "C:\dir""#).unwrap(),
+            vec!["ECHO", "say \"hi\" \\", "C:\\dir"]
+        );
+        assert_eq!(tokenize(r#"ECHO """#).unwrap(), vec!["ECHO", ""]);
+        assert!(tokenize(r#"SET k "open"#).is_err());
+    }
+
+    #[test]
+    fn other_backslashes_in_quotes_are_kept() {
+        let mut db = Database::new();
+        run(&mut db, r#"SET path str "C:\new\dir\t""#);
+        assert_eq!(run(&mut db, "GET path"), value(r"path: C:\new\dir\t"));
+    }
+
+    #[test]
+    fn set_stores_quoted_text_exactly() {
+        let mut db = Database::new();
+        run(&mut db, r#"SET k str "  two  spaces ""#);
+        assert_eq!(
+            run(&mut db, "GET k"),
+            CommandResponse::Value("k:   two  spaces ".into())
+        );
+    }
+
+    #[test]
+    fn quote_round_trips_through_tokenize() {
+        for token in [
+            "plain",
+            "",
+            "two words",
+            "#hash",
+            "a\"b",
+            "back\\slash",
+            "cr\r\ttab",
+            "bell\u{7}",
+            "literal \\n",
+        ] {
+            let quoted = quote(token);
+            assert_eq!(tokenize(&quoted).unwrap(), vec![token.to_string()]);
+        }
+    }
+
+    #[test]
+    fn dumpall_rebuilds_the_database_through_run() {
+        let mut db = Database::new();
+        for line in [
+            r#"SET text str "two  spaces, \"quotes\" and C:\dir""#,
+            "SET empty str \"\"",
+            "SET n int -42",
+            "SET f float -0.0",
+            "SET big float 1e300",
+            "SET flag bool true",
+            "SET u uint 18446744073709551615",
+            "SET blob bytes 00ff10",
+            "SET nothing null",
+            "SET when datetime 2024-01-02T03:04:05Z",
+            "RPUSH list int 1",
+            "RPUSH list int 2",
+            r#"HSET h "a field" "a value""#,
+            "HSET h other x",
+        ] {
+            assert!(!is_error(&run(&mut db, line)), "{}", line);
+        }
+        // No command line can hold a line break, so DUMPALL writes this one
+        // as a RESTORE blob.
+        db.set("lines".to_string(), DbValue::from_str("line1\nline2\r"));
+        let CommandResponse::Value(script) = run(&mut db, "DUMPALL") else {
+            panic!("DUMPALL gave no script");
+        };
+
+        let before = db.clone();
+        run(&mut db, "CLEAR CONFIRM");
+        assert_eq!(db.count(), 0);
+
+        let path = temp_path("dumpall.txt");
+        fs::write(&path, &script).unwrap();
+        assert!(!is_error(&run(&mut db, &format!("RUN {}", path))));
+        let _ = fs::remove_file(path);
+
+        assert_eq!(db.keys(), before.keys());
+        for key in before.keys() {
+            let (a, b) = (before.mget(&[key])[0].unwrap(), db.mget(&[key])[0].unwrap());
+            assert_eq!((&a.typetag, &a.data), (&b.typetag, &b.data), "{}", key);
+        }
+        assert_eq!(run(&mut db, "DUMPALL"), CommandResponse::Value(script));
+    }
+
+    #[test]
+    fn set_needs_a_key_and_a_value() {
+        let mut db = Database::new();
+        for line in ["SET", "SET k", "SET k int"] {
+            let response = run(&mut db, line);
+            let CommandResponse::Error(e) = &response else {
+                panic!("{} gave {:?}", line, response);
+            };
+            assert!(e.starts_with("Usage: SET <key> [type] <value>"), "{}", e);
+        }
+        assert_eq!(db.count(), 0);
+
+        assert_eq!(
+            run(&mut db, "SET k int 5"),
+            CommandResponse::Ok(String::new())
+        );
+        assert_eq!(db.get("k").and_then(DbValue::as_int), Some(5));
+    }
+
+    #[test]
+    fn delete_removes_a_key() {
+        let mut db = Database::new();
+        run(&mut db, "SET k 1");
+        assert_eq!(
+            run(&mut db, "DELETE k"),
+            CommandResponse::Ok("Deleted k".into())
+        );
+        assert_eq!(
+            run(&mut db, "GET k"),
+            CommandResponse::Error("Key not found".into())
+        );
+        assert_eq!(
+            run(&mut db, "DELETE k"),
+            CommandResponse::Error("Key not found".into())
+        );
+    }
+
+    #[test]
+    fn keys_lists_every_key_sorted() {
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, "KEYS"),
+            CommandResponse::Value("(empty)".into())
+        );
+        for key in ["pear", "apple", "fig"] {
+            run(&mut db, &format!("SET {} 1", key));
+        }
+        assert_eq!(db.keys(), vec!["apple", "fig", "pear"]);
+        assert_eq!(
+            run(&mut db, "KEYS"),
+            CommandResponse::Value("apple\nfig\npear".into())
+        );
+    }
+
+    #[test]
+    fn exists_reports_presence() {
+        let mut db = Database::new();
+        run(&mut db, "SET here 1");
+        assert!(db.exists("here"));
+        assert!(!db.exists("gone"));
+        assert_eq!(
+            run(&mut db, "EXISTS here"),
+            CommandResponse::Value("true".into())
+        );
+        assert_eq!(
+            run(&mut db, "EXISTS gone"),
+            CommandResponse::Value("false".into())
+        );
+    }
+
+    #[test]
+    fn type_names_each_type() {
+        let mut db = Database::new();
+        for (line, expected) in [
+            ("SET k str text", "str"),
+            ("SET k int 1", "int"),
+            ("SET k float 1.5", "float"),
+            ("SET k bool true", "bool"),
+            ("SET k uint 1", "uint"),
+            ("SET k bytes ff", "bytes"),
+            ("SET k null", "null"),
+            ("SET k datetime 2024-01-01T00:00:00Z", "datetime"),
+        ] {
+            run(&mut db, line);
+            assert_eq!(run(&mut db, "TYPE k"), value(expected), "{}", line);
+        }
+        run(&mut db, "RPUSH list a");
+        assert_eq!(run(&mut db, "TYPE list"), value("array"));
+        run(&mut db, "HSET h f v");
+        assert_eq!(run(&mut db, "TYPE h"), value("hash"));
+        assert_eq!(run(&mut db, "TYPE missing"), error("Key not found"));
+    }
+
+    #[test]
+    fn incr_creates_and_increments() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "INCR n"), value("1"));
+        assert_eq!(run(&mut db, "INCR n"), value("2"));
+        assert_eq!(run(&mut db, "GET n"), value("n: 2"));
+    }
+
+    #[test]
+    fn incr_refuses_to_overflow() {
+        let mut db = Database::new();
+        run(&mut db, &format!("SET n int {}", i64::MAX));
+        assert_eq!(
+            run(&mut db, "INCR n"),
+            error("Result would overflow an integer")
+        );
+        assert_eq!(db.get("n").and_then(DbValue::as_int), Some(i64::MAX));
+    }
+
+    #[test]
+    fn incr_needs_an_int() {
+        let mut db = Database::new();
+        run(&mut db, "SET s str 1");
+        run(&mut db, "SET f float 1.5");
+        for line in ["INCR s", "INCR f"] {
+            assert_eq!(run(&mut db, line), error("Value is not an integer"));
+        }
+        assert_eq!(run(&mut db, "GET s"), value("s: 1"));
+    }
+
+    #[test]
+    fn decr_and_incrby_take_either_sign() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "DECR n"), value("-1"));
+        assert_eq!(run(&mut db, "INCRBY n 10"), value("9"));
+        assert_eq!(run(&mut db, "INCRBY n -4"), value("5"));
+        assert_eq!(run(&mut db, "DECRBY n 7"), value("-2"));
+        assert_eq!(run(&mut db, "DECRBY n -3"), value("1"));
+    }
+
+    #[test]
+    fn decr_refuses_to_underflow() {
+        let mut db = Database::new();
+        run(&mut db, &format!("SET n int {}", i64::MIN));
+        for line in ["DECR n", "DECRBY n 1", "INCRBY n -1"] {
+            assert_eq!(
+                run(&mut db, line),
+                error("Result would overflow an integer"),
+                "{}",
+                line
+            );
+        }
+        // Negating i64::MIN overflows on its own.
+        run(&mut db, "SET m int 0");
+        assert_eq!(
+            run(&mut db, &format!("DECRBY m {}", i64::MIN)),
+            error("Result would overflow an integer")
+        );
+        assert_eq!(db.get("n").and_then(DbValue::as_int), Some(i64::MIN));
+    }
+
+    #[test]
+    fn incrby_needs_an_integer_amount() {
+        let mut db = Database::new();
+        run(&mut db, "SET n int 1");
+        assert_eq!(run(&mut db, "INCRBY n 1.5"), error("not a valid integer"));
+        assert_eq!(run(&mut db, "DECRBY n abc"), error("not a valid integer"));
+        assert_eq!(
+            run(&mut db, "INCRBY n 99999999999999999999"),
+            error("integer out of range for i64")
+        );
+        assert_eq!(run(&mut db, "GET n"), value("n: 1"));
+    }
+
+    #[test]
+    fn append_extends_strings() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "APPEND s hello"), value("5"));
+        assert_eq!(run(&mut db, r#"APPEND s " world""#), value("11"));
+        assert_eq!(run(&mut db, "GET s"), value("s: hello world"));
+
+        run(&mut db, "SET n int 1");
+        assert_eq!(run(&mut db, "APPEND n 2"), error("Value is not a string"));
+        assert_eq!(run(&mut db, "GET n"), value("n: 1"));
+    }
+
+    #[test]
+    fn count_follows_sets_and_deletes() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "COUNT"), value("0"));
+        for line in ["SET a 1", "SET b 2", "SET c 3", "SET a 4"] {
+            run(&mut db, line);
+        }
+        assert_eq!(run(&mut db, "COUNT"), value("3"));
+        run(&mut db, "DELETE b");
+        assert_eq!(run(&mut db, "DBSIZE"), value("2"));
+    }
+
+    #[test]
+    fn clear_needs_confirmation() {
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        run(&mut db, "SET b 2");
+        assert_eq!(
+            run(&mut db, "CLEAR"),
+            error("This removes every key. Use: CLEAR CONFIRM")
+        );
+        assert_eq!(
+            run(&mut db, "clear yes"),
+            error("This removes every key. Use: CLEAR CONFIRM")
+        );
+        assert_eq!(db.count(), 2);
+
+        assert_eq!(
+            run(&mut db, "CLEAR confirm"),
+            CommandResponse::Ok("Removed 2 keys".into())
+        );
+        assert_eq!(db.count(), 0);
+    }
+
+    #[test]
+    fn rename_moves_a_value() {
+        let mut db = Database::new();
+        run(&mut db, "SET old 1");
+        assert_eq!(
+            run(&mut db, "RENAME old new"),
+            CommandResponse::Ok("Renamed old to new".into())
+        );
+        assert!(!db.exists("old"));
+        assert_eq!(run(&mut db, "GET new"), value("new: 1"));
+    }
+
+    #[test]
+    fn rename_needs_the_source() {
+        let mut db = Database::new();
+        run(&mut db, "SET other 1");
+        assert_eq!(run(&mut db, "RENAME missing other"), error("Key not found"));
+        assert_eq!(run(&mut db, "GET other"), value("other: 1"));
+    }
+
+    #[test]
+    fn rename_says_when_it_overwrites() {
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        run(&mut db, "SET b 2");
+        assert_eq!(
+            run(&mut db, "RENAME a b"),
+            CommandResponse::Ok("Renamed a to b (overwrote existing value)".into())
+        );
+        assert_eq!(db.keys(), vec!["b"]);
+        assert_eq!(run(&mut db, "GET b"), value("b: 1"));
+    }
+
+    #[test]
+    fn null_keys_exist() {
+        let mut db = Database::new();
+        run(&mut db, "SET nothing null");
+        assert_eq!(run(&mut db, "EXISTS nothing"), value("true"));
+        assert_eq!(run(&mut db, "EXISTS missing"), value("false"));
+        assert_eq!(run(&mut db, "GET nothing"), value("nothing: (nil)"));
+        assert_eq!(run(&mut db, "GET missing"), error("Key not found"));
+        assert!(is_error(&run(&mut db, "SET k null value")));
+    }
+
+    #[test]
+    fn handle_command_returns_the_response_text() {
+        let mut db = Database::new();
+        let shown = |db: &mut Database, input: &[&str]| handle_command(db, input).to_string();
+        assert_eq!(shown(&mut db, &["GET", "k"]), "ERR Key not found");
+        assert_eq!(shown(&mut db, &["SET", "k", "1"]), "OK");
+        assert_eq!(shown(&mut db, &["GET", "k"]), "k: 1");
+        assert_eq!(shown(&mut db, &["FROB", "k"]), "ERR Unknown command");
+        assert_eq!(shown(&mut db, &[]), "OK");
+    }
+
+    #[test]
+    fn every_command_and_alias_is_handled() {
+        // These are answered by the front end.
+        let front_end = ["STATUS", "HISTORY", "QUIT"];
+        let names = COMMANDS
+            .iter()
+            .map(|(name, _, _)| *name)
+            .chain(ALIASES.iter().map(|(alias, _)| *alias))
+            .chain(HIDDEN.iter().copied());
+        for name in names {
+            let command = canonical_command(name);
+            assert_ne!(command, "", "{} doesn't resolve", name);
+            let mut db = Database::new();
+            assert_eq!(
+                handle_command(&mut db, &[name]) == error("Unknown command"),
+                front_end.contains(&command),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn help_lists_every_command() {
+        let CommandResponse::Value(text) = help(None) else {
+            panic!("HELP failed");
+        };
+        assert_eq!(text.lines().count(), COMMANDS.len());
+        for (line, (_, usage, description)) in text.lines().zip(COMMANDS) {
+            assert!(line.starts_with(usage), "{}", line);
+            assert!(line.ends_with(description), "{}", line);
+        }
+    }
+
+    #[test]
+    fn help_describes_one_command() {
+        assert_eq!(
+            help(Some("get")),
+            value("Usage: GET <key>\nPrint the value stored at a key")
+        );
+        // Aliases find the command they stand for.
+        assert!(
+            matches!(help(Some("DEL")), CommandResponse::Value(text) if text.starts_with("Usage: DELETE"))
+        );
+        assert_eq!(help(Some("FROB")), error("Unknown command: FROB"));
+    }
+
+    #[test]
+    fn extra_whitespace_and_tabs_separate_tokens() {
+        assert_eq!(
+            tokenize("  SET   k\tINT \t 5  ").unwrap(),
+            vec!["SET", "k", "INT", "5"]
+        );
+        assert!(tokenize(" \t ").unwrap().is_empty());
+
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, "\tSET  k  Int\t\t7 "),
+            CommandResponse::Ok(String::new())
+        );
+        assert_eq!(run(&mut db, "TYPE k"), value("int"));
+        run(&mut db, "set s STRING  two   words ");
+        // Unquoted words are joined with single spaces.
+        assert_eq!(run(&mut db, "GET s"), value("s: two words"));
+    }
+
+    #[test]
+    fn mget_mixes_hits_and_misses() {
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        run(&mut db, "SET c str three");
+        assert_eq!(
+            run(&mut db, "MGET a b c"),
+            value("a: 1\nb: (nil)\nc: three")
+        );
+        assert_eq!(run(&mut db, "MGET missing"), value("missing: (nil)"));
+        assert!(is_error(&run(&mut db, "MGET")));
+    }
+
+    #[test]
+    fn mset_sets_a_batch() {
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, r#"MSET a int 1 b str "two words" c bool true"#),
+            CommandResponse::Ok("3 keys set".into())
+        );
+        assert_eq!(
+            run(&mut db, "MGET a b c"),
+            value("a: 1\nb: two words\nc: true")
+        );
+    }
+
+    #[test]
+    fn mset_changes_nothing_when_one_value_is_bad() {
+        let mut db = Database::new();
+        run(&mut db, "SET a int 0");
+        assert_eq!(
+            run(&mut db, "MSET a int 1 b int x c int 3"),
+            error("Triple 2 (b int x) is invalid: not a valid integer")
+        );
+        assert_eq!(run(&mut db, "GET a"), value("a: 0"));
+        assert_eq!(db.count(), 1);
+    }
+
+    #[test]
+    fn mset_rejects_an_incomplete_triple() {
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, "MSET a int 1 b int"),
+            error("Triple 2 (b int) is incomplete: expected <key> <type> <value>")
+        );
+        assert_eq!(db.count(), 0);
+    }
+
+    #[test]
+    fn scan_lists_matching_keys() {
+        let mut db = Database::new();
+        for key in ["user:1", "user:22", "item:1", "user"] {
+            run(&mut db, &format!("SET {} 1", key));
+        }
+        assert_eq!(
+            run(&mut db, "SCAN *"),
+            value("item:1\nuser\nuser:1\nuser:22")
+        );
+        assert_eq!(run(&mut db, "SCAN user:?"), value("user:1"));
+        assert_eq!(run(&mut db, "SCAN user"), value("user"));
+        assert_eq!(run(&mut db, "SCAN nothing*"), value("(empty)"));
+    }
+
+    #[test]
+    fn copy_duplicates_a_value() {
+        let mut db = Database::new();
+        run(&mut db, "SET src int 5");
+        assert_eq!(
+            run(&mut db, "COPY src dst"),
+            CommandResponse::Ok("Copied src to dst".into())
+        );
+        assert_eq!(run(&mut db, "MGET src dst"), value("src: 5\ndst: 5"));
+        // The copy is separate from the original.
+        run(&mut db, "INCR dst");
+        assert_eq!(run(&mut db, "MGET src dst"), value("src: 5\ndst: 6"));
+    }
+
+    #[test]
+    fn copy_needs_the_source() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "COPY missing dst"), error("Key not found"));
+        assert!(!db.exists("dst"));
+    }
+
+    #[test]
+    fn copy_only_overwrites_with_replace() {
+        let mut db = Database::new();
+        run(&mut db, "SET src 1");
+        run(&mut db, "SET dst 2");
+        assert_eq!(
+            run(&mut db, "COPY src dst"),
+            error("dst already exists (use REPLACE to overwrite)")
+        );
+        assert_eq!(run(&mut db, "GET dst"), value("dst: 2"));
+        assert!(!is_error(&run(&mut db, "COPY src dst replace")));
+        assert_eq!(run(&mut db, "GET dst"), value("dst: 1"));
+    }
+
+    #[test]
+    fn set_rejects_blank_and_control_character_keys() {
+        let mut db = Database::new();
+        for key in [r#""""#, r#""   ""#, "\"a\tb\"", "\"bell\u{7}\""] {
+            assert_eq!(
+                run(&mut db, &format!("SET {} 1", key)),
+                error(INVALID_KEY),
+                "{}",
+                key
+            );
+        }
+        assert_eq!(db.count(), 0);
+        // Spaces inside a key are fine.
+        assert!(!is_error(&run(&mut db, r#"SET "a b" 1"#)));
+        assert!(is_valid_key("a b"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("line\nbreak"));
+    }
+
+    #[test]
+    fn getset_returns_the_old_value() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "GETSET k 1"), value("(nil)"));
+        assert_eq!(run(&mut db, "GETSET k str two"), value("1"));
+        assert_eq!(run(&mut db, "GET k"), value("k: two"));
+    }
+
+    #[test]
+    fn setnx_only_sets_missing_keys() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "SETNX k 1"), value("1"));
+        assert_eq!(run(&mut db, "SETNX k str other"), value("0"));
+        assert_eq!(run(&mut db, "GET k"), value("k: 1"));
+        assert_eq!(run(&mut db, "TYPE k"), value("int"));
+    }
+
+    #[test]
+    fn decode_line_reports_invalid_utf8() {
+        assert_eq!(decode_line("GET ké".as_bytes()), Ok("GET ké"));
+        assert_eq!(
+            decode_line(b"GET \xc3"),
+            Err(error("invalid utf-8 in command"))
+        );
+    }
+
+    #[test]
+    fn responses_carry_their_prefix() {
+        let mut db = Database::new();
+        let shown = |db: &mut Database, line: &str| run(db, line).to_string();
+        assert_eq!(shown(&mut db, "SET k 1"), "OK");
+        assert_eq!(shown(&mut db, "DELETE k"), "OK Deleted k");
+        assert_eq!(shown(&mut db, "DELETE k"), "ERR Key not found");
+        assert_eq!(shown(&mut db, "INCR s"), "1");
+        assert!(shown(&mut db, "SET").starts_with("ERR Usage: SET <key>"));
+        assert_eq!(shown(&mut db, "PING"), "PONG");
+    }
+
+    #[test]
+    fn recent_lists_the_newest_insertions() {
+        let mut db = Database::new();
+        for key in ["first", "second", "third"] {
+            run(&mut db, &format!("SET {} 1", key));
+        }
+        assert_eq!(run(&mut db, "RECENT 2"), value("third\nsecond"));
+        // An overwrite counts as a new insertion; a read doesn't.
+        run(&mut db, "SET first 2");
+        run(&mut db, "GET second");
+        assert_eq!(run(&mut db, "RECENT 2"), value("first\nthird"));
+        run(&mut db, "DELETE first");
+        assert_eq!(run(&mut db, "RECENT 5"), value("third\nsecond"));
+        assert_eq!(run(&mut db, "RECENT 0"), value("(empty)"));
+    }
+
+    #[test]
+    fn strlen_counts_bytes() {
+        let mut db = Database::new();
+        run(&mut db, "SET ascii str hello");
+        run(&mut db, "SET accented str héllo");
+        run(&mut db, "SET n int 12345");
+        assert_eq!(run(&mut db, "STRLEN ascii"), value("5"));
+        assert_eq!(run(&mut db, "STRLEN accented"), value("6"));
+        assert_eq!(run(&mut db, "STRLEN n"), error("Value is not a string"));
+        assert_eq!(run(&mut db, "STRLEN missing"), error("Key not found"));
+    }
+
+    #[test]
+    fn strcount_counts_characters() {
+        let mut db = Database::new();
+        run(&mut db, "SET accented str héllo");
+        run(&mut db, "SET emoji str 🦀");
+        assert_eq!(run(&mut db, "STRLEN accented"), value("6"));
+        assert_eq!(run(&mut db, "STRCOUNT accented"), value("5"));
+        assert_eq!(run(&mut db, "STRLEN emoji"), value("4"));
+        assert_eq!(run(&mut db, "STRCOUNT emoji"), value("1"));
+    }
+
+    #[test]
+    fn range_includes_both_bounds() {
+        let mut db = Database::new();
+        for line in [
+            "SET low int 1",
+            "SET mid int 5",
+            "SET also_five int 5",
+            "SET high int 10",
+            "SET over int 11",
+            "SET text str 5",
+            "SET f float 5.0",
+        ] {
+            run(&mut db, line);
+        }
+        assert_eq!(
+            run(&mut db, "RANGE 1 10"),
+            value("low\nalso_five\nmid\nhigh")
+        );
+        assert_eq!(run(&mut db, "RANGE 5 5"), value("also_five\nmid"));
+        assert_eq!(run(&mut db, "RANGE 12 20"), value("(empty)"));
+        assert_eq!(run(&mut db, "RANGE 10 1"), value("(empty)"));
+        assert_eq!(run(&mut db, "RANGE a 1"), error("not a valid integer"));
+    }
+
+    #[test]
+    fn case_insensitive_keys_fold_to_lowercase() {
+        let mut db = Database::new();
+        db.set_case_insensitive(true);
+        run(&mut db, "SET user 1");
+        assert_eq!(run(&mut db, "GET USER"), value("user: 1"));
+        run(&mut db, "SET User 2");
+        assert_eq!(db.keys(), vec!["user"]);
+        assert_eq!(run(&mut db, "GET uSeR"), value("user: 2"));
+    }
+
+    #[test]
+    fn keys_are_case_sensitive_by_default() {
+        let mut db = Database::new();
+        run(&mut db, "SET user 1");
+        run(&mut db, "SET USER 2");
+        assert_eq!(db.keys(), vec!["USER", "user"]);
+        assert_eq!(run(&mut db, "GET user"), value("user: 1"));
+    }
+
+    #[test]
+    fn truncate_cuts_at_character_boundaries() {
+        let mut db = Database::new();
+        run(&mut db, "SET s str héllo");
+        // "hé" is 3 bytes.
+        assert_eq!(run(&mut db, "TRUNCATE s 3"), value("3"));
+        assert_eq!(run(&mut db, "GET s"), value("s: hé"));
+        // Byte 2 is inside "é", so the cut backs off to 1.
+        assert_eq!(run(&mut db, "TRUNCATE s 2"), value("1"));
+        assert_eq!(run(&mut db, "GET s"), value("s: h"));
+        assert!(db.get("s").and_then(DbValue::as_string).is_some());
+    }
+
+    #[test]
+    fn truncate_past_the_end_changes_nothing() {
+        let mut db = Database::new();
+        run(&mut db, "SET s str abc");
+        assert_eq!(run(&mut db, "TRUNCATE s 10"), value("3"));
+        assert_eq!(run(&mut db, "GET s"), value("s: abc"));
+        run(&mut db, "SET n int 1");
+        assert_eq!(run(&mut db, "TRUNCATE n 0"), error("Value is not a string"));
+        assert_eq!(run(&mut db, "TRUNCATE missing 0"), error("Key not found"));
+    }
+
+    #[test]
+    fn dump_and_restore_round_trip() {
+        let mut db = Database::new();
+        for line in [
+            r#"SET s str "some text""#,
+            "SET n int -1",
+            "SET f float 2.5",
+            "RPUSH list int 3",
+            "HSET h field text",
+        ] {
+            run(&mut db, line);
+        }
+        let before = db.clone();
+        let blobs = ["s", "n", "f", "list", "h"].map(|key| {
+            let CommandResponse::Value(blob) = run(&mut db, &format!("DUMP {}", key)) else {
+                panic!("DUMP {} failed", key);
+            };
+            (key, blob)
+        });
+
+        run(&mut db, "CLEAR CONFIRM");
+        for (key, blob) in &blobs {
+            assert!(!is_error(&run(
+                &mut db,
+                &format!("RESTORE {} {}", key, blob)
+            )));
+        }
+        for (key, _) in blobs {
+            let (a, b) = (before.mget(&[key])[0].unwrap(), db.mget(&[key])[0].unwrap());
+            assert_eq!((&a.typetag, &a.data), (&b.typetag, &b.data), "{}", key);
+        }
+    }
+
+    #[test]
+    fn dump_blobs_are_the_disk_layout_in_hex() {
+        let mut db = Database::new();
+        run(&mut db, "SET s str hi");
+        assert_eq!(run(&mut db, "DUMP s"), value("00000000026869"));
+        assert_eq!(run(&mut db, "DUMP missing"), error("Key not found"));
+    }
+
+    #[test]
+    fn restore_rejects_bad_blobs() {
+        let mut db = Database::new();
+        for (blob, e) in [
+            ("zz", "Invalid blob: not hex"),
+            ("ff00000000", "Invalid blob: unknown typetag 255"),
+            ("0000", "Invalid blob: truncated length"),
+            ("000000000268", "Invalid blob: length does not match data"),
+        ] {
+            assert_eq!(run(&mut db, &format!("RESTORE k {}", blob)), error(e));
+        }
+        assert!(!db.exists("k"));
+    }
+
+    #[test]
+    fn max_keys_evicts_the_least_recently_used() {
+        let mut db = Database::new();
+        db.set_max_keys(Some(3));
+        for key in ["a", "b", "c"] {
+            run(&mut db, &format!("SET {} 1", key));
+        }
+        // `a` is now the most recently used, so `b` goes first.
+        run(&mut db, "GET a");
+        run(&mut db, "SET d 1");
+        assert_eq!(db.keys(), vec!["a", "c", "d"]);
+        run(&mut db, "SET e 1");
+        assert_eq!(db.keys(), vec!["a", "d", "e"]);
+        // Overwriting an existing key evicts nothing.
+        run(&mut db, "SET a 2");
+        assert_eq!(db.count(), 3);
+    }
+
+    #[test]
+    fn touched_keys_survive_eviction() {
+        let mut db = Database::new();
+        db.set_max_keys(Some(2));
+        run(&mut db, "SET old 1");
+        run(&mut db, "SET new 1");
+        assert_eq!(run(&mut db, "TOUCH old"), value("1"));
+        run(&mut db, "SET newest 1");
+        assert_eq!(db.keys(), vec!["newest", "old"]);
+        assert_eq!(run(&mut db, "TOUCH missing"), value("0"));
+    }
+
+    #[test]
+    fn comment_lines_have_no_tokens() {
+        assert!(tokenize("# SET k 1").unwrap().is_empty());
+        assert!(tokenize("   #comment").unwrap().is_empty());
+        // Only a leading # starts a comment.
+        assert_eq!(tokenize("SET k #1").unwrap(), vec!["SET", "k", "#1"]);
+    }
+
+    #[test]
+    fn run_executes_a_script() {
+        let path = temp_path("run-script.txt");
+        fs::write(&path, "SET a 1\nSET b 2\n# comment\nGET a\nDELETE b\n").unwrap();
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, &format!("RUN {}", path)),
+            value("OK\nOK\na: 1\nOK Deleted b")
+        );
+        assert_eq!(db.keys(), vec!["a"]);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn run_stops_at_the_first_error_unless_told_not_to() {
+        let path = temp_path("run-errors.txt");
+        fs::write(&path, "SET a 1\nDELETE missing\nSET b 2\n").unwrap();
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, &format!("RUN {}", path)),
+            value(&format!(
+                "OK\nERR Key not found\nERR Stopped at line 2 of {}",
+                path
+            ))
+        );
+        assert!(!db.exists("b"));
+
+        run(&mut db, &format!("RUN {} --continue-on-error", path));
+        assert!(db.exists("b"));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn run_refuses_nesting_and_missing_files() {
+        let path = temp_path("run-nested.txt");
+        fs::write(&path, format!("RUN {}\n", path)).unwrap();
+        let mut db = Database::new();
+        let CommandResponse::Value(output) = run(&mut db, &format!("RUN {}", path)) else {
+            panic!("nested RUN gave no output");
+        };
+        assert!(output.starts_with("ERR RUN cannot be nested"), "{}", output);
+        let _ = fs::remove_file(&path);
+
+        assert!(is_error(&run(&mut db, &format!("RUN {}", path))));
+    }
+
+    #[test]
+    fn aliases_resolve_to_their_command() {
+        for (alias, command) in ALIASES {
+            assert_eq!(canonical_command(alias), *command);
+            assert_eq!(canonical_command(&alias.to_lowercase()), *command);
+            assert!(COMMANDS.iter().any(|(name, _, _)| name == command));
+        }
+        assert_eq!(canonical_command("get"), "GET");
+        assert_eq!(canonical_command("Tokens"), "TOKENS");
+        assert_eq!(canonical_command("nope"), "");
+    }
+
+    #[test]
+    fn aliases_run_their_command() {
+        let mut db = Database::new();
+        run(&mut db, "SET k 1");
+        assert_eq!(run(&mut db, "dbsize"), value("1"));
+        assert_eq!(
+            run(&mut db, "Del k"),
+            CommandResponse::Ok("Deleted k".into())
+        );
+    }
+
+    #[test]
+    fn findval_matches_substrings_of_strings() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "FINDVAL any"), value("(empty)"));
+        run(&mut db, r#"SET greeting str "Hello world""#);
+        run(&mut db, "SET other str goodbye");
+        run(&mut db, "SET n str 42");
+        run(&mut db, "SET i int 42");
+        assert_eq!(run(&mut db, "FINDVAL o w"), value("greeting"));
+        assert_eq!(run(&mut db, "FINDVAL o"), value("greeting\nother"));
+        assert_eq!(run(&mut db, "FINDVAL hello"), value("(empty)"));
+        // Only strings are searched.
+        assert_eq!(run(&mut db, "FINDVAL 42"), value("n"));
+    }
+
+    #[test]
+    fn findvali_ignores_case_without_changing_values() {
+        let mut db = Database::new();
+        run(&mut db, "SET a str HeLLo");
+        run(&mut db, "SET b str bye");
+        assert_eq!(run(&mut db, "FINDVALI hello"), value("a"));
+        assert_eq!(run(&mut db, "FINDVALI LL"), value("a"));
+        assert_eq!(run(&mut db, "FINDVAL hello"), value("(empty)"));
+        assert_eq!(run(&mut db, "GET a"), value("a: HeLLo"));
+    }
+
+    #[test]
+    fn ping_replies_pong_or_echoes() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "PING"), value("PONG"));
+        assert_eq!(run(&mut db, "ping hello there"), value("hello there"));
+    }
+
+    #[test]
+    fn echo_returns_its_arguments() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "ECHO a   b"), value("a b"));
+        assert_eq!(run(&mut db, r#"ECHO "a   b""#), value("a   b"));
+        assert_eq!(run(&mut db, "ECHO"), value(""));
+    }
+
+    #[test]
+    fn tokens_shows_how_a_line_splits() {
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, r#"TOKENS SET k "two  words" "say \"hi\"""#),
+            value("1: \"SET\"\n2: \"k\"\n3: \"two  words\"\n4: \"say \\\"hi\\\"\"")
+        );
+    }
+
+    #[test]
+    fn stats_group_keys_by_type() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "STATS"), value("(empty)"));
+        for line in [
+            "SET a int 1",
+            "SET b int 2",
+            "SET c str abc",
+            "SET d bool true",
+            "SET e str hello",
+        ] {
+            run(&mut db, line);
+        }
+        assert_eq!(
+            run(&mut db, "STATS"),
+            value("bool: 1 keys, 1 bytes\nint: 2 keys, 16 bytes\nstr: 2 keys, 8 bytes")
+        );
+    }
+
+    #[test]
+    fn sort_orders_ints_and_floats_together() {
+        let mut db = Database::new();
+        for line in [
+            "SET n:a int 3",
+            "SET n:b float 1.5",
+            "SET n:c int -2",
+            "SET n:d float 3.0",
+            "SET n:e str 0",
+            "SET other int 0",
+        ] {
+            run(&mut db, line);
+        }
+        // n:a and n:d are equal, so they stay in key order both ways.
+        assert_eq!(
+            run(&mut db, "SORT n:*"),
+            value("n:c -2\nn:b 1.5\nn:a 3\nn:d 3")
+        );
+        assert_eq!(
+            run(&mut db, "SORT n:* desc"),
+            value("n:a 3\nn:d 3\nn:b 1.5\nn:c -2")
+        );
+        assert_eq!(run(&mut db, "SORT x*"), value("(empty)"));
+        assert!(is_error(&run(&mut db, "SORT n:* UP")));
+    }
+
+    #[test]
+    fn readonly_allows_reads_and_refuses_writes() {
+        let mut db = Database::new();
+        run(&mut db, "SET k 1");
+        db.set_readonly(true);
+
+        assert_eq!(run(&mut db, "GET k"), value("k: 1"));
+        assert_eq!(run(&mut db, "KEYS"), value("k"));
+        for line in [
+            "SET k 2",
+            "DELETE k",
+            "INCR k",
+            "CLEAR CONFIRM",
+            "EXPIRE k 5",
+        ] {
+            assert_eq!(
+                run(&mut db, line),
+                error("database is read-only"),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(run(&mut db, "GET k"), value("k: 1"));
+    }
+
+    #[test]
+    fn push_builds_an_array_from_both_ends() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "RPUSH l int 2"), value("1"));
+        assert_eq!(run(&mut db, "RPUSH l int 3"), value("2"));
+        assert_eq!(run(&mut db, "LPUSH l int 1"), value("3"));
+        assert_eq!(run(&mut db, "GET l"), value("l: [1, 2, 3]"));
+        assert_eq!(
+            run(&mut db, "RPUSH l str x"),
+            error("Array holds int values")
+        );
+        run(&mut db, "SET s str text");
+        assert_eq!(run(&mut db, "RPUSH s x"), error("Value is not an array"));
+    }
+
+    #[test]
+    fn lrange_reads_ranges_and_negative_indices() {
+        let mut db = Database::new();
+        for item in ["a", "b", "c", "d"] {
+            run(&mut db, &format!("RPUSH l {}", item));
+        }
+        assert_eq!(run(&mut db, "LRANGE l 0 -1"), value("a\nb\nc\nd"));
+        assert_eq!(run(&mut db, "LRANGE l 1 2"), value("b\nc"));
+        assert_eq!(run(&mut db, "LRANGE l -2 -1"), value("c\nd"));
+        assert_eq!(run(&mut db, "LRANGE l -100 100"), value("a\nb\nc\nd"));
+        assert_eq!(run(&mut db, "LRANGE l 3 1"), value("(empty)"));
+        assert_eq!(run(&mut db, "LRANGE missing 0 1"), error("Key not found"));
+    }
+
+    #[test]
+    fn hset_adds_and_overwrites_fields() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "HSET h name Ada"), value("1"));
+        assert_eq!(run(&mut db, "HSET h lang Rust"), value("1"));
+        assert_eq!(run(&mut db, r#"HSET h name "Ada L""#), value("0"));
+        assert_eq!(run(&mut db, "HGET h name"), value("Ada L"));
+        assert_eq!(run(&mut db, "HGET h age"), error("Field not found"));
+        // Fields keep the order they were first set in.
+        assert_eq!(run(&mut db, "HGETALL h"), value("name: Ada L\nlang: Rust"));
+    }
+
+    #[test]
+    fn hash_commands_need_a_hash() {
+        let mut db = Database::new();
+        run(&mut db, "SET s str text");
+        assert_eq!(run(&mut db, "HSET s f v"), error("Value is not a hash"));
+        assert_eq!(run(&mut db, "HGET s f"), error("Value is not a hash"));
+        assert_eq!(run(&mut db, "HGETALL s"), error("Value is not a hash"));
+        assert_eq!(run(&mut db, "HGETALL missing"), error("Key not found"));
+    }
+
+    #[test]
+    fn strict_types_refuse_a_set_that_changes_the_type() {
+        let mut db = Database::new();
+        db.set_strict_types(true);
+        run(&mut db, "SET n int 1");
+        assert_eq!(
+            run(&mut db, "SET n int 2"),
+            CommandResponse::Ok(String::new())
+        );
+        assert_eq!(
+            run(&mut db, "SET n str two"),
+            error("Type mismatch: n holds int, not str (DELETE it first)")
+        );
+        assert_eq!(
+            run(&mut db, "SET n int 3.5"),
+            error("Type mismatch: '3.5' is float, not int")
+        );
+        assert_eq!(run(&mut db, "GET n"), value("n: 2"));
+
+        // Without the flag the type just changes.
+        db.set_strict_types(false);
+        run(&mut db, "SET n str two");
+        assert_eq!(run(&mut db, "TYPE n"), value("str"));
+    }
+
+    #[test]
+    fn getrange_takes_positive_and_negative_indices() {
+        let mut db = Database::new();
+        run(&mut db, r#"SET s str "hello world""#);
+        assert_eq!(run(&mut db, "GETRANGE s 0 4"), value("hello"));
+        assert_eq!(run(&mut db, "GETRANGE s -5 -1"), value("world"));
+        assert_eq!(run(&mut db, "GETRANGE s 6 -1"), value("world"));
+    }
+
+    #[test]
+    fn getrange_clamps_out_of_range_indices() {
+        let mut db = Database::new();
+        run(&mut db, "SET s str abc");
+        assert_eq!(run(&mut db, "GETRANGE s -100 100"), value("abc"));
+        assert_eq!(run(&mut db, "GETRANGE s 2 1"), value(""));
+        assert_eq!(run(&mut db, "GETRANGE s 5 10"), value(""));
+        run(&mut db, "SET e str é");
+        assert_eq!(
+            run(&mut db, "GETRANGE e 0 0"),
+            error("Range splits a UTF-8 character")
+        );
+    }
+
+    #[test]
+    fn setrange_overwrites_within_the_string() {
+        let mut db = Database::new();
+        run(&mut db, r#"SET s str "hello world""#);
+        assert_eq!(run(&mut db, "SETRANGE s 6 there"), value("11"));
+        assert_eq!(run(&mut db, "GET s"), value("s: hello there"));
+    }
+
+    #[test]
+    fn setrange_pads_past_the_end() {
+        let mut db = Database::new();
+        run(&mut db, "SET s str ab");
+        assert_eq!(run(&mut db, "SETRANGE s 4 cd"), value("6"));
+        assert_eq!(run(&mut db, "GET s"), value("s: ab  cd"));
+        assert_eq!(run(&mut db, "SETRANGE new 2 x"), value("3"));
+        assert_eq!(run(&mut db, "GET new"), value("new:   x"));
+    }
+
+    #[test]
+    fn setrange_refuses_huge_offsets() {
+        let mut db = Database::new();
+        run(&mut db, "SET s str ab");
+        assert_eq!(
+            run(
+                &mut db,
+                &format!("SETRANGE s {} x", crate::MAX_SETRANGE_LEN)
+            ),
+            error("String would exceed 16.0 MB")
+        );
+        assert!(is_error(&run(
+            &mut db,
+            &format!("SETRANGE s {} x", usize::MAX)
+        )));
+        assert_eq!(run(&mut db, "GET s"), value("s: ab"));
+    }
+
+    #[test]
+    fn responses_render_as_json() {
+        assert_eq!(
+            CommandResponse::Ok(String::new()).to_json(),
+            r#"{"ok":true}"#
+        );
+        assert_eq!(
+            CommandResponse::Ok("Saved".into()).to_json(),
+            r#"{"ok":true,"message":"Saved"}"#
+        );
+        assert_eq!(value("42").to_json(), r#"{"ok":true,"value":42}"#);
+        // Only numbers that print back the same stay unquoted.
+        assert_eq!(value("042").to_json(), r#"{"ok":true,"value":"042"}"#);
+        assert_eq!(
+            value("k: \"a\"").to_json(),
+            r#"{"ok":true,"value":"k: \"a\""}"#
+        );
+        assert_eq!(
+            error("Key not found").to_json(),
+            r#"{"ok":false,"error":"Key not found"}"#
+        );
+    }
+
+    #[test]
+    fn debug_lists_keys_in_order() {
+        let mut db = Database::new();
+        for key in ["b", "c", "a"] {
+            run(&mut db, &format!("SET {} str x", key));
+        }
+        let CommandResponse::Value(text) = run(&mut db, "DEBUG") else {
+            panic!("DEBUG failed");
+        };
+        let keys = text
+            .lines()
+            .map(|line| line.split(' ').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+        assert_eq!(text.lines().next(), Some("a (str): x  hex: 0078"));
+    }
+
+    #[test]
+    fn swap_exchanges_two_values() {
+        let mut db = Database::new();
+        run(&mut db, "SET a str front");
+        run(&mut db, "SET b int 2");
+        assert_eq!(
+            run(&mut db, "SWAP a b"),
+            CommandResponse::Ok("Swapped a and b".into())
+        );
+        assert_eq!(run(&mut db, "GET a"), value("a: 2"));
+        assert_eq!(run(&mut db, "GET b"), value("b: front"));
+        assert_eq!(run(&mut db, "COUNT"), value("2"));
+        assert!(!is_error(&run(&mut db, "SWAP a a")));
+        assert_eq!(run(&mut db, "GET a"), value("a: 2"));
+    }
+
+    #[test]
+    fn swap_with_a_missing_key_changes_nothing() {
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        assert_eq!(run(&mut db, "SWAP a x"), error("Key not found: x"));
+        assert_eq!(run(&mut db, "SWAP x a"), error("Key not found: x"));
+        assert_eq!(run(&mut db, "GET a"), value("a: 1"));
+        assert_eq!(run(&mut db, "EXISTS x"), value("false"));
+    }
+
+    #[test]
+    fn randomkey_picks_a_live_key() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, "RANDOMKEY"), value("(nil)"));
+
+        for key in ["a", "b", "c"] {
+            run(&mut db, &format!("SET {} 1", key));
+        }
+        db.seed_rng(42);
+        let picks = (0..20)
+            .map(|_| run(&mut db, "RANDOMKEY"))
+            .collect::<Vec<_>>();
+        db.seed_rng(42);
+        for pick in &picks {
+            assert_eq!(&run(&mut db, "RANDOMKEY"), pick);
+            assert!(["a", "b", "c"].map(value).contains(pick), "{:?}", pick);
+        }
+
+        run(&mut db, "DEL a");
+        run(&mut db, "DEL b");
+        assert_eq!(run(&mut db, "RANDOMKEY"), value("c"));
+    }
+
+    #[test]
+    fn databases_keep_their_own_keys() {
+        let mut db = Database::new();
+        run(&mut db, "SET k str zero");
+        assert_eq!(run(&mut db, "SELECT 1"), CommandResponse::Ok(String::new()));
+        assert_eq!(run(&mut db, "GET k"), error("Key not found"));
+        run(&mut db, "SET k str one");
+        assert_eq!(run(&mut db, "COUNT"), value("1"));
+
+        run(&mut db, "SELECT 0");
+        assert_eq!(run(&mut db, "GET k"), value("k: zero"));
+        assert_eq!(
+            run(&mut db, &format!("SELECT {}", crate::DATABASES)),
+            error(&format!(
+                "Database index must be 0 to {}",
+                crate::DATABASES - 1
+            ))
+        );
+        assert_eq!(run(&mut db, "SELECT x"), error("Invalid database: x"));
+        assert_eq!(db.selected(), 0);
+    }
+
+    #[test]
+    fn move_only_moves_into_a_free_key() {
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        run(&mut db, "SET b 2");
+        assert_eq!(run(&mut db, "MOVE a 1"), value("1"));
+        assert_eq!(run(&mut db, "EXISTS a"), value("false"));
+        assert_eq!(run(&mut db, "MOVE missing 1"), value("0"));
+        assert_eq!(
+            run(&mut db, "MOVE b 0"),
+            error("Source and destination databases are the same")
+        );
+
+        run(&mut db, "SELECT 1");
+        assert_eq!(run(&mut db, "GET a"), value("a: 1"));
+        run(&mut db, "SET b 20");
+        // Already there, so nothing moves.
+        assert_eq!(run(&mut db, "MOVE b 0"), value("0"));
+        run(&mut db, "SELECT 0");
+        assert_eq!(run(&mut db, "GET b"), value("b: 2"));
+    }
+
+    #[test]
+    fn swapdb_exchanges_whole_databases() {
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        run(&mut db, "SELECT 2");
+        run(&mut db, "SET b 2");
+        run(&mut db, "SET c 3");
+
+        assert_eq!(
+            run(&mut db, "SWAPDB 0 2"),
+            CommandResponse::Ok("Swapped databases 0 and 2".into())
+        );
+        // Still on database 2, which now holds what 0 had.
+        assert_eq!(db.selected(), 2);
+        assert_eq!(db.keys(), vec!["a"]);
+        run(&mut db, "SELECT 0");
+        assert_eq!(db.keys(), vec!["b", "c"]);
+
+        // With an empty one, too.
+        run(&mut db, "SWAPDB 0 5");
+        assert_eq!(db.count(), 0);
+        run(&mut db, "SELECT 5");
+        assert_eq!(db.keys(), vec!["b", "c"]);
+        assert_eq!(run(&mut db, "SWAPDB 0 x"), error("Invalid database: x"));
+        assert!(is_error(&run(&mut db, "SWAPDB 0 99")));
+    }
+
+    #[test]
+    fn raw_dumps_the_stored_bytes() {
+        let mut db = Database::new();
+        run(&mut db, "SET s str hello");
+        assert_eq!(
+            run(&mut db, "RAW s"),
+            value("str (tag 0), 5 bytes\n0000: 68656c6c 6f")
+        );
+        run(&mut db, "SET n int 1");
+        assert_eq!(
+            run(&mut db, "RAW n"),
+            value("int (tag 1), 8 bytes\n0000: 00000000 00000001")
+        );
+        run(&mut db, "SET z null");
+        assert_eq!(
+            run(&mut db, "RAW z"),
+            value("null (tag 6), 0 bytes\n(no data)")
+        );
+        assert_eq!(run(&mut db, "RAW missing"), error("Key not found"));
+    }
+
+    #[test]
+    fn hex_dump_breaks_lines_every_16_bytes() {
+        let data = (0..20).collect::<Vec<u8>>();
+        assert_eq!(
+            hex_dump(&data),
+            "0000: 00010203 04050607 08090a0b 0c0d0e0f\n0010: 10111213"
+        );
+    }
+
+    #[test]
+    fn debug_stops_at_the_limit() {
+        let mut db = Database::new();
+        for i in 0..5 {
+            run(&mut db, &format!("SET k{} 1", i));
+        }
+        let CommandResponse::Value(text) = run(&mut db, "DEBUG 2") else {
+            panic!("DEBUG failed");
+        };
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("k0 "), "{}", lines[0]);
+        assert!(lines[1].starts_with("k1 "), "{}", lines[1]);
+        assert_eq!(lines[2], "... 3 more");
+
+        let CommandResponse::Value(text) = run(&mut db, "DEBUG 5") else {
+            panic!("DEBUG failed");
+        };
+        assert!(!text.contains("more"));
+        assert_eq!(run(&mut db, "DEBUG 0"), value("... 5 more"));
+        assert_eq!(run(&mut db, "DEBUG x"), error("Invalid count: x"));
+    }
+
+    #[test]
+    fn debug_defaults_to_debug_limit() {
+        let mut db = Database::new();
+        for i in 0..DEBUG_LIMIT + 1 {
+            run(&mut db, &format!("SET k{:03} 1", i));
+        }
+        let CommandResponse::Value(text) = run(&mut db, "DEBUG") else {
+            panic!("DEBUG failed");
+        };
+        assert_eq!(text.lines().count(), DEBUG_LIMIT + 1);
+        assert_eq!(text.lines().last(), Some("... 1 more"));
+    }
+
+    #[test]
+    fn keysoftype_lists_keys_holding_each_type() {
+        let mut db = Database::new();
+        for line in [
+            "SET s1 str a",
+            "SET s2 str b",
+            "SET i int 1",
+            "SET f float 1.5",
+            "SET b bool true",
+            "SET u uint 1",
+            "SET x bytes ff",
+            "SET n null",
+            "SET d datetime 2000-01-01T00:00:00Z",
+            "RPUSH l a",
+            "HSET h field v",
+        ] {
+            assert!(!is_error(&run(&mut db, line)), "{}", line);
+        }
+
+        assert_eq!(run(&mut db, "KEYSOFTYPE str"), value("s1\ns2"));
+        for (name, key) in [
+            ("int", "i"),
+            ("float", "f"),
+            ("bool", "b"),
+            ("uint", "u"),
+            ("bytes", "x"),
+            ("null", "n"),
+            ("datetime", "d"),
+            ("array", "l"),
+            ("hash", "h"),
+        ] {
+            assert_eq!(
+                run(&mut db, &format!("KEYSOFTYPE {}", name)),
+                value(key),
+                "{}",
+                name
+            );
+        }
+        run(&mut db, "DEL i");
+        assert_eq!(run(&mut db, "KEYSOFTYPE int"), value("(empty)"));
+    }
+
+    #[test]
+    fn keysoftype_rejects_unknown_types() {
+        let mut db = Database::new();
+        let CommandResponse::Error(e) = run(&mut db, "KEYSOFTYPE colour") else {
+            panic!("expected an error");
+        };
+        assert!(e.starts_with("Unknown type: colour. Use: "), "{}", e);
+        assert!(e.ends_with(", array, hash"), "{}", e);
+    }
+
+    #[test]
+    fn delmatch_removes_only_matching_keys() {
+        let mut db = Database::new();
+        for key in ["user:1", "user:2", "session:1"] {
+            run(&mut db, &format!("SET {} 1", key));
+        }
+        assert_eq!(
+            run(&mut db, "DELMATCH user:*"),
+            CommandResponse::Ok("Removed 2 keys".into())
+        );
+        assert_eq!(run(&mut db, "KEYS"), value("session:1"));
+        assert_eq!(
+            run(&mut db, "DELMATCH nothing*"),
+            CommandResponse::Ok("Removed 0 keys".into())
+        );
+        assert_eq!(run(&mut db, "COUNT"), value("1"));
+    }
+
+    #[test]
+    fn delmatch_everything_needs_confirm() {
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        run(&mut db, "SET b 2");
+        for pattern in ["*", "**"] {
+            assert_eq!(
+                run(&mut db, &format!("DELMATCH {}", pattern)),
+                error(&format!(
+                    "This removes every key. Use: DELMATCH {} CONFIRM",
+                    pattern
+                ))
+            );
+        }
+        assert_eq!(run(&mut db, "COUNT"), value("2"));
+        assert_eq!(
+            run(&mut db, "DELMATCH * confirm"),
+            CommandResponse::Ok("Removed 2 keys".into())
+        );
+        assert_eq!(run(&mut db, "COUNT"), value("0"));
+    }
+
+    #[test]
+    fn cas_sets_only_on_a_match() {
+        let mut db = Database::new();
+        run(&mut db, "SET k int 1");
+        assert_eq!(run(&mut db, "CAS k int 2 3"), value("0"));
+        assert_eq!(run(&mut db, "GET k"), value("k: 1"));
+        // Compared as parsed values, not as text.
+        assert_eq!(run(&mut db, "CAS k int 01 3"), value("1"));
+        assert_eq!(run(&mut db, "GET k"), value("k: 3"));
+        // Same bytes under another type don't count.
+        assert_eq!(run(&mut db, "CAS k uint 3 4"), value("0"));
+
+        assert_eq!(run(&mut db, "CAS missing int 1 2"), value("0"));
+        assert_eq!(run(&mut db, "EXISTS missing"), value("false"));
+
+        run(&mut db, "SET s str old");
+        assert_eq!(run(&mut db, "CAS s str old new value"), value("1"));
+        assert_eq!(run(&mut db, "GET s"), value("s: new value"));
+        assert!(is_error(&run(&mut db, "CAS k int x 1")));
+    }
+
+    #[test]
+    fn case_insensitive_rename_compares_folded_keys() {
+        let mut db = Database::new();
+        db.set_case_insensitive(true);
+        run(&mut db, "SET Foo 1");
+        assert_eq!(
+            run(&mut db, "RENAME Foo foo"),
+            CommandResponse::Ok("Renamed foo to foo".into())
+        );
+        assert_eq!(run(&mut db, "GET FOO"), value("foo: 1"));
+
+        run(&mut db, "SET bar 2");
+        assert_eq!(
+            run(&mut db, "RENAME FOO Bar"),
+            CommandResponse::Ok("Renamed foo to bar (overwrote existing value)".into())
+        );
+        assert_eq!(db.keys(), vec!["bar"]);
+    }
+
+    #[test]
+    fn flushall_clears_every_database() {
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        run(&mut db, "SELECT 2");
+        run(&mut db, "SET b 2");
+        run(&mut db, "SET c 3");
+        assert_eq!(
+            run(&mut db, "FLUSHALL"),
+            error("This removes every key in every database. Use: FLUSHALL CONFIRM")
+        );
+        assert_eq!(
+            run(&mut db, "CLEAR CONFIRM"),
+            CommandResponse::Ok("Removed 2 keys".into())
+        );
+        run(&mut db, "SET d 4");
+        assert_eq!(
+            run(&mut db, "FLUSHALL CONFIRM"),
+            CommandResponse::Ok("Removed 2 keys".into())
+        );
+        assert_eq!(db.count(), 0);
+        assert_eq!(db.selected(), 2);
+        run(&mut db, "SELECT 0");
+        assert_eq!(db.count(), 0);
+    }
+
+    #[test]
+    fn dumpall_covers_every_database_and_null_items() {
+        let mut db = Database::new();
+        run(&mut db, "SET a 1");
+        run(&mut db, r#"RPUSH nils null """#);
+        run(&mut db, r#"RPUSH nils null """#);
+        run(&mut db, "SELECT 4");
+        run(&mut db, "SET b str two");
+        let CommandResponse::Value(script) = run(&mut db, "DUMPALL") else {
+            panic!("DUMPALL gave no script");
+        };
+        assert_eq!(
+            script,
+            "SET a int 1\nRPUSH nils null \"\"\nRPUSH nils null \"\"\nSELECT 4\nSET b str two"
+        );
+
+        let path = temp_path("dumpall-databases.txt");
+        fs::write(&path, &script).unwrap();
+        let mut copy = Database::new();
+        assert!(!is_error(&run(&mut copy, &format!("RUN {}", path))));
+        let _ = fs::remove_file(path);
+
+        assert_eq!(run(&mut copy, "GET b"), value("b: two"));
+        run(&mut copy, "SELECT 0");
+        assert_eq!(run(&mut copy, "LRANGE nils 0 -1"), value("(nil)\n(nil)"));
+        assert_eq!(run(&mut copy, "GET a"), value("a: 1"));
+    }
+}
@@ -0,0 +1,114 @@
+// Just enough CSV for IMPORTCSV and EXPORTCSV: comma-separated fields,
+// where a field in double quotes may hold commas, newlines and `""` for a
+// quote.
+
+use std::borrow::Cow;
+
+// Quotes a field only when it needs it.
+pub fn field(text: &str) -> Cow<'_, str> {
+    if text.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", text.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => {
+                // Quoted field: read up to the closing quote.
+                let opened = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => return Err(format!("line {}: unterminated quote", opened)),
+                    }
+                }
+                if !matches!(chars.peek(), None | Some(',' | '\n' | '\r')) {
+                    return Err(format!("line {}: text after closing quote", line));
+                }
+            }
+            ',' => fields.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                push_row(&mut rows, std::mem::take(&mut fields));
+                line += 1;
+            }
+            c => field.push(c),
+        }
+    }
+
+    fields.push(field);
+    push_row(&mut rows, fields);
+    Ok(rows)
+}
+
+// Blank lines are not rows.
+fn push_row(rows: &mut Vec<Vec<String>>, fields: Vec<String>) {
+    if fields.len() == 1 && fields[0].is_empty() {
+        return;
+    }
+    rows.push(fields);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(text: &str) -> Vec<Vec<String>> {
+        parse(text).unwrap()
+    }
+
+    #[test]
+    fn fields_are_quoted_only_when_needed() {
+        assert_eq!(field("plain"), "plain");
+        assert_eq!(field("a,b"), "\"a,b\"");
+        assert_eq!(field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn parse_splits_rows_and_fields() {
+        assert_eq!(rows("a,b\r\nc,d\n\n"), vec![vec!["a", "b"], vec!["c", "d"]]);
+        assert_eq!(rows("a,,\n"), vec![vec!["a", "", ""]]);
+        assert!(rows("").is_empty());
+    }
+
+    #[test]
+    fn quoted_fields_keep_commas_quotes_and_newlines() {
+        assert_eq!(
+            rows("k,\"a,b\"\nj,\"say \"\"hi\"\"\"\ni,\"x\ny\"\n"),
+            vec![vec!["k", "a,b"], vec!["j", "say \"hi\""], vec!["i", "x\ny"]]
+        );
+    }
+
+    #[test]
+    fn bad_quotes_name_the_line() {
+        assert_eq!(
+            parse("a,b\nc,\"open\n"),
+            Err("line 2: unterminated quote".to_string())
+        );
+        assert_eq!(
+            parse("\"a\"b,c\n"),
+            Err("line 1: text after closing quote".to_string())
+        );
+    }
+}
@@ -0,0 +1,161 @@
+// UTC timestamps in the ISO-8601 form `YYYY-MM-DDTHH:MM:SSZ`, converted
+// to and from Unix seconds.
+
+const FORMAT_HINT: &str = "use YYYY-MM-DDTHH:MM:SSZ";
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The inverse of days_from_civil.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// Reads a fixed-width run of ASCII digits.
+fn number(text: &str, range: std::ops::Range<usize>) -> Result<i64, String> {
+    text.get(range)
+        .filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|digits| digits.parse::<i64>().ok())
+        .ok_or_else(|| format!("Invalid datetime ({})", FORMAT_HINT))
+}
+
+// The trailing `Z` may be left out; the time is read as UTC either way.
+pub fn parse(text: &str) -> Result<i64, String> {
+    let text = text.strip_suffix('Z').unwrap_or(text);
+    let bytes = text.as_bytes();
+    if bytes.len() != 19
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return Err(format!("Invalid datetime ({})", FORMAT_HINT));
+    }
+
+    let year = number(text, 0..4)?;
+    let month = number(text, 5..7)?;
+    let day = number(text, 8..10)?;
+    let hour = number(text, 11..13)?;
+    let minute = number(text, 14..16)?;
+    let second = number(text, 17..19)?;
+
+    if !(1..=12).contains(&month) {
+        return Err(format!("Invalid datetime: month {} is out of range", month));
+    }
+    if !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(format!(
+            "Invalid datetime: {:04}-{:02} has no day {}",
+            year, month, day
+        ));
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err("Invalid datetime: time is out of range".to_string());
+    }
+
+    Ok(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+pub fn format(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let secs = timestamp.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_valid_timestamps() {
+        assert_eq!(parse("1970-01-01T00:00:00Z"), Ok(0));
+        assert_eq!(parse("2024-01-02T03:04:05Z"), Ok(1_704_164_645));
+        // The Z is optional and the T may be lowercase.
+        assert_eq!(parse("2024-01-02t03:04:05"), Ok(1_704_164_645));
+        assert_eq!(parse("1969-12-31T23:59:59Z"), Ok(-1));
+    }
+
+    #[test]
+    fn accepts_leap_days_only_in_leap_years() {
+        assert_eq!(
+            format(parse("2024-02-29T12:00:00Z").unwrap()),
+            "2024-02-29T12:00:00Z"
+        );
+        assert!(parse("2000-02-29T00:00:00Z").is_ok());
+        assert_eq!(
+            parse("2023-02-29T00:00:00Z"),
+            Err("Invalid datetime: 2023-02 has no day 29".to_string())
+        );
+        assert!(parse("1900-02-29T00:00:00Z").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_formats() {
+        for text in [
+            "",
+            "2024-01-02",
+            "2024-01-02 03:04:05",
+            "2024/01/02T03:04:05Z",
+            "24-01-02T03:04:05Z",
+            "2024-13-02T03:04:05Z",
+            "2024-01-00T03:04:05Z",
+            "2024-01-02T24:00:00Z",
+            "2024-01-02T03:60:00Z",
+            "2024-01-02T03:04:05+01:00",
+            "2024-0a-02T03:04:05Z",
+        ] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn format_is_the_inverse_of_parse() {
+        for timestamp in [0, -1, 951_782_400, 4_102_444_799] {
+            assert_eq!(parse(&format(timestamp)), Ok(timestamp));
+        }
+    }
+}
@@ -0,0 +1,60 @@
+// Matches `key` against a pattern where `*` is any run of characters
+// (including none) and `?` is exactly one character.
+pub fn glob_match(pattern: &str, key: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let key = key.chars().collect::<Vec<char>>();
+
+    let (mut p, mut k) = (0, 0);
+    // Where the last `*` was seen, and the key position it was tried at.
+    let mut star: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == key[k]) {
+            p += 1;
+            k += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, k));
+            p += 1;
+        } else if let Some((star_p, star_k)) = star {
+            // Let the last `*` swallow one more character and retry.
+            p = star_p + 1;
+            k = star_k + 1;
+            star = Some((star_p, k));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("user:*", "user:"));
+        assert!(glob_match("user:*", "user:42"));
+        assert!(glob_match("*:*:end", "a:b:c:end"));
+        assert!(!glob_match("user:*", "users"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob_match("k?", "k1"));
+        assert!(glob_match("k?", "ké"));
+        assert!(!glob_match("k?", "k"));
+        assert!(!glob_match("k?", "k12"));
+    }
+
+    #[test]
+    fn other_characters_match_literally() {
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "Exact"));
+        assert!(!glob_match("exact", "exactly"));
+        assert!(!glob_match("", "x"));
+    }
+}
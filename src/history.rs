@@ -0,0 +1,138 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+};
+
+// The last `capacity` command lines, mirrored to a file so they survive
+// restarts.
+pub struct History {
+    path: Option<String>,
+    capacity: usize,
+    entries: VecDeque<String>,
+}
+
+impl History {
+    pub fn new(path: Option<&str>, capacity: usize) -> Self {
+        History {
+            path: path.map(str::to_string),
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    // Reads the history file if it exists, keeping only the newest lines.
+    pub fn load(path: &str, capacity: usize) -> io::Result<Self> {
+        let mut history = History::new(Some(path), capacity);
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                for line in text.lines() {
+                    history.remember(line);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(history)
+    }
+
+    fn remember(&mut self, line: &str) {
+        if self.capacity == 0 || line.trim().is_empty() {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(line.to_string());
+    }
+
+    // Records a line and appends it to the file right away, so a crash
+    // doesn't lose the session.
+    pub fn push(&mut self, line: &str) -> io::Result<()> {
+        let line = line.trim_end();
+        self.remember(line);
+
+        if let Some(path) = &self.path
+            && self.capacity > 0
+        {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    // Rewrites the file with just the kept lines, so it doesn't grow forever.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut text = String::new();
+        for entry in &self.entries {
+            let _ = writeln!(text, "{}", entry);
+        }
+        fs::write(path, text)
+    }
+
+    // Numbered listing for the HISTORY command, oldest first.
+    pub fn listing(&self) -> String {
+        if self.entries.is_empty() {
+            return "(empty)".to_string();
+        }
+
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| format!("{:>4}  {}", i + 1, entry))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_path;
+
+    #[test]
+    fn survives_a_reload() {
+        let path = temp_path("history-reload");
+        let _ = fs::remove_file(&path);
+
+        let mut history = History::load(&path, 10).unwrap();
+        for line in ["SET a 1", "GET a\n", "  ", "DELETE a"] {
+            history.push(line).unwrap();
+        }
+        history.save().unwrap();
+
+        let history = History::load(&path, 10).unwrap();
+        assert_eq!(
+            history.listing(),
+            "   1  SET a 1\n   2  GET a\n   3  DELETE a"
+        );
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn keeps_only_the_newest_lines() {
+        let path = temp_path("history-capacity");
+        let _ = fs::remove_file(&path);
+
+        let mut history = History::load(&path, 2).unwrap();
+        for line in ["one", "two", "three"] {
+            history.push(line).unwrap();
+        }
+        assert_eq!(history.listing(), "   1  two\n   2  three");
+        // The file holds every line until save trims it.
+        assert_eq!(History::load(&path, 5).unwrap().entries.len(), 3);
+        history.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two\nthree\n");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn empty_history_lists_nothing() {
+        assert_eq!(History::new(None, 10).listing(), "(empty)");
+    }
+}
@@ -0,0 +1,3653 @@
+mod autosave;
+mod clock;
+mod commands;
+mod csv;
+mod datetime;
+mod glob;
+mod history;
+mod json;
+mod rng;
+mod signal;
+#[cfg(test)]
+mod testing;
+
+pub use autosave::Autosave;
+pub use clock::Clock;
+use commands::expire_line;
+pub use commands::{
+    CommandResponse, canonical_command, decode_line, handle_command, quote, quote_line, tokenize,
+};
+pub use datetime::format as format_datetime;
+pub use glob::glob_match;
+pub use history::History;
+use json::JsonValue;
+use rng::Rng;
+pub use signal::{install_signal_handlers, shutdown_requested};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    mem,
+    num::IntErrorKind,
+    str,
+    sync::mpsc::Sender,
+    time::{Duration, SystemTime},
+};
+
+// Identifies a connected client for WATCH notifications.
+pub type ClientId = u64;
+
+// SETRANGE won't grow a string past this many bytes.
+const MAX_SETRANGE_LEN: usize = 16 * 1024 * 1024;
+
+// Every saved file starts with these bytes.
+const MAGIC: &[u8; 4] = b"PILL";
+
+// Written right after MAGIC. Bump it when the entry layout changes and
+// teach `migrate` about the old one.
+//   1: bools were stored as the text `true` / `false`.
+//   2: bools are a single byte, 1 or 0.
+//   3: entries can be split by database, see `save`.
+const FORMAT_VERSION: u8 = 3;
+
+// How many numbered databases SELECT can switch between.
+pub const DATABASES: usize = 16;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum DataType {
+    String = 0,
+    Int = 1,
+    Float = 2,
+    Bool = 3,
+    UInt = 4,
+    Bytes = 5,
+    Null = 6,
+    // Unix seconds, shown as ISO-8601 UTC.
+    DateTime = 7,
+    // A list of values that all share one type. Built with LPUSH and RPUSH
+    // rather than SET.
+    Array = 8,
+    // String fields mapped to string values, kept in insertion order. Built
+    // with HSET.
+    Hash = 9,
+}
+
+// Names accepted by SET and IMPORT, as reported by TYPE.
+pub const TYPE_NAMES: &[&str] = &[
+    "str", "int", "float", "bool", "uint", "bytes", "null", "datetime",
+];
+
+impl DataType {
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(DataType::String),
+            1 => Some(DataType::Int),
+            2 => Some(DataType::Float),
+            3 => Some(DataType::Bool),
+            4 => Some(DataType::UInt),
+            5 => Some(DataType::Bytes),
+            6 => Some(DataType::Null),
+            7 => Some(DataType::DateTime),
+            8 => Some(DataType::Array),
+            9 => Some(DataType::Hash),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataType::String => "str",
+            DataType::Int => "int",
+            DataType::Float => "float",
+            DataType::Bool => "bool",
+            DataType::UInt => "uint",
+            DataType::Bytes => "bytes",
+            DataType::Null => "null",
+            DataType::DateTime => "datetime",
+            DataType::Array => "array",
+            DataType::Hash => "hash",
+        }
+    }
+
+    // The inverse of `name`, also taking the aliases SET accepts.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        let name = match name.as_str() {
+            "string" => "str",
+            "i64" => "int",
+            "f64" => "float",
+            "u64" => "uint",
+            name => name,
+        };
+        (0..=u8::MAX)
+            .filter_map(DataType::from_tag)
+            .find(|t| t.name() == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DbValue {
+    pub typetag: DataType,
+    pub data: Vec<u8>,
+    // Set by EXPIRE. Expired values are dropped the next time they are read.
+    // A wall-clock time, so it still holds after a restart.
+    pub expires_at: Option<SystemTime>,
+    // The window EXPIRE was given, so TOUCH can restart it.
+    pub ttl: Option<Duration>,
+}
+
+impl DbValue {
+    // == To set data types easily. ==
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        DbValue {
+            typetag: DataType::String,
+            data: s.as_bytes().to_vec(),
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    pub fn from_i64(i: i64) -> Self {
+        DbValue {
+            typetag: DataType::Int,
+            data: i.to_be_bytes().to_vec(),
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    pub fn from_f64(f: f64) -> Self {
+        DbValue {
+            typetag: DataType::Float,
+            data: f.to_be_bytes().to_vec(),
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    pub fn from_u64(u: u64) -> Self {
+        DbValue {
+            typetag: DataType::UInt,
+            data: u.to_be_bytes().to_vec(),
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    pub fn from_bool(b: bool) -> Self {
+        DbValue {
+            typetag: DataType::Bool,
+            data: vec![b as u8],
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        DbValue {
+            typetag: DataType::Bytes,
+            data: bytes.to_vec(),
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    // A key that exists but holds nothing.
+    pub fn null() -> Self {
+        DbValue {
+            typetag: DataType::Null,
+            data: Vec::new(),
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    pub fn from_datetime(timestamp: i64) -> Self {
+        DbValue {
+            typetag: DataType::DateTime,
+            data: timestamp.to_be_bytes().to_vec(),
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    // == Arrays ==
+    // [item typetag: u8], then for each item:
+    // [data len: u32, big-endian][data bytes]
+
+    // `items` must be non-empty and all of one type.
+    pub fn from_array(items: &[DbValue]) -> Self {
+        let mut data = vec![items[0].typetag.clone() as u8];
+        for item in items {
+            data.extend_from_slice(&(item.data.len() as u32).to_be_bytes());
+            data.extend_from_slice(&item.data);
+        }
+
+        DbValue {
+            typetag: DataType::Array,
+            data,
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    // The item type and the items, or None if this isn't a well-formed array.
+    pub fn array_items(&self) -> Option<(DataType, Vec<DbValue>)> {
+        if self.typetag != DataType::Array {
+            return None;
+        }
+        let (&tag, mut rest) = self.data.split_first()?;
+        let item_type = DataType::from_tag(tag).filter(|t| *t != DataType::Array)?;
+
+        let mut items = Vec::new();
+        while !rest.is_empty() {
+            let (len, tail) = rest.split_first_chunk::<4>()?;
+            let len = u32::from_be_bytes(*len) as usize;
+            if tail.len() < len {
+                return None;
+            }
+            items.push(DbValue {
+                typetag: item_type.clone(),
+                data: tail[..len].to_vec(),
+                expires_at: None,
+                ttl: None,
+            });
+            rest = &tail[len..];
+        }
+        Some((item_type, items))
+    }
+
+    // == Hashes ==
+    // For each field: [field len: u32][field bytes][value len: u32][value bytes]
+    // Lengths are big-endian.
+
+    pub fn from_hash(fields: &[(String, String)]) -> Self {
+        let mut data = Vec::new();
+        for (field, value) in fields {
+            for part in [field, value] {
+                data.extend_from_slice(&(part.len() as u32).to_be_bytes());
+                data.extend_from_slice(part.as_bytes());
+            }
+        }
+
+        DbValue {
+            typetag: DataType::Hash,
+            data,
+            expires_at: None,
+            ttl: None,
+        }
+    }
+
+    // The fields in insertion order, or None if this isn't a well-formed hash.
+    pub fn hash_fields(&self) -> Option<Vec<(String, String)>> {
+        if self.typetag != DataType::Hash {
+            return None;
+        }
+
+        let mut rest = self.data.as_slice();
+        let mut next_part = || {
+            let (len, tail) = rest.split_first_chunk::<4>()?;
+            let len = u32::from_be_bytes(*len) as usize;
+            let part = str::from_utf8(tail.get(..len)?).ok()?.to_string();
+            rest = &tail[len..];
+            Some(part)
+        };
+
+        let mut fields = Vec::new();
+        while let Some(field) = next_part() {
+            fields.push((field, next_part()?));
+        }
+        // Anything left over didn't parse as a field.
+        rest.is_empty().then_some(fields)
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        Ok(DbValue::from_bytes(&from_hex(hex)?))
+    }
+
+    // == DUMP blobs ==
+    // Hex of [typetag: u8][data len: u32, big-endian][data bytes], the same
+    // layout a value has on disk. Expiry is not included.
+
+    pub fn dump(&self) -> String {
+        let mut blob = vec![self.typetag.clone() as u8];
+        blob.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        blob.extend_from_slice(&self.data);
+        to_hex(&blob)
+    }
+
+    pub fn restore(hex: &str) -> Result<Self, String> {
+        let blob = from_hex(hex).map_err(|_| "Invalid blob: not hex".to_string())?;
+        let (&tag, rest) = blob.split_first().ok_or("Invalid blob: empty")?;
+        let typetag = DataType::from_tag(tag)
+            .ok_or_else(|| format!("Invalid blob: unknown typetag {}", tag))?;
+
+        let (len, data) = rest
+            .split_first_chunk::<4>()
+            .ok_or("Invalid blob: truncated length")?;
+        if u32::from_be_bytes(*len) as usize != data.len() {
+            return Err("Invalid blob: length does not match data".to_string());
+        }
+
+        Ok(DbValue {
+            typetag,
+            data: data.to_vec(),
+            expires_at: None,
+            ttl: None,
+        })
+    }
+
+    // Builds a value from a type name and its text form, as typed in SET.
+    pub fn parse(value_type: &str, value_str: &str) -> Result<Self, String> {
+        match value_type.to_lowercase().as_str() {
+            "str" | "string" => Ok(DbValue::from_str(value_str)),
+            "int" | "i64" => parse_int(value_str).map(DbValue::from_i64),
+            "float" | "f64" => parse_float(value_str).map(DbValue::from_f64),
+            "bool" => value_str
+                .parse::<bool>()
+                .map(DbValue::from_bool)
+                .map_err(|_| "Invalid boolean value (use 'true' or 'false')".to_string()),
+            "uint" | "u64" => parse_uint(value_str).map(DbValue::from_u64),
+            "bytes" => DbValue::from_hex(value_str),
+            "datetime" => datetime::parse(value_str).map(DbValue::from_datetime),
+            "null" if value_str.is_empty() => Ok(DbValue::null()),
+            "null" => Err("null takes no value".to_string()),
+            _ => Err(format!("Invalid type. Use: {}", TYPE_NAMES.join(", "))),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.typetag.name()
+    }
+
+    // Equal as decoded values rather than bytes, so 0.0 matches -0.0.
+    pub fn same_value(&self, other: &DbValue) -> bool {
+        match (self.as_float(), other.as_float()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.typetag == other.typetag && self.data == other.data,
+        }
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    // == To get data types easily. ==
+
+    pub fn as_string(&self) -> Option<&str> {
+        if self.typetag == DataType::String {
+            // Data loaded from disk may not be valid UTF-8.
+            str::from_utf8(&self.data).ok()
+        } else {
+            None
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        if self.typetag == DataType::Int && self.data.len() == 8 {
+            // i64 and f64 are always 8 bytes.
+            Some(i64::from_be_bytes(self.data[..8].try_into().unwrap()))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        if self.typetag == DataType::Float && self.data.len() == 8 {
+            // i64 and f64 are always 8 bytes.
+            Some(f64::from_be_bytes(self.data[..8].try_into().unwrap()))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        if self.typetag == DataType::UInt && self.data.len() == 8 {
+            Some(u64::from_be_bytes(self.data[..8].try_into().unwrap()))
+        } else {
+            None
+        }
+    }
+
+    // Unix seconds.
+    pub fn as_datetime(&self) -> Option<i64> {
+        if self.typetag == DataType::DateTime && self.data.len() == 8 {
+            Some(i64::from_be_bytes(self.data[..8].try_into().unwrap()))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        if self.typetag == DataType::Bytes {
+            Some(&self.data)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        if self.typetag == DataType::Bool && self.data.len() == 1 {
+            Some(self.data[0] != 0)
+        } else {
+            None
+        }
+    }
+
+    // Decodes the value for display. Data that doesn't match the typetag,
+    // say from a corrupt file, is an error rather than a panic.
+    pub fn to_display_string(&self) -> Result<String, String> {
+        let bad_length = || {
+            format!(
+                "invalid {} data ({} bytes)",
+                self.type_name(),
+                self.data.len()
+            )
+        };
+
+        match self.typetag {
+            DataType::String => self
+                .as_string()
+                .map(str::to_string)
+                .ok_or_else(|| "invalid utf-8".to_string()),
+            DataType::Int => self.as_int().map(|i| i.to_string()).ok_or_else(bad_length),
+            DataType::Float => self
+                .as_float()
+                .map(|f| f.to_string())
+                .ok_or_else(bad_length),
+            DataType::Bool => self.as_bool().map(|b| b.to_string()).ok_or_else(bad_length),
+            DataType::UInt => self.as_u64().map(|u| u.to_string()).ok_or_else(bad_length),
+            DataType::Bytes => Ok(to_hex(&self.data)),
+            DataType::Null => Ok("(nil)".to_string()),
+            DataType::DateTime => self
+                .as_datetime()
+                .map(datetime::format)
+                .ok_or_else(bad_length),
+            DataType::Array => {
+                let (_, items) = self
+                    .array_items()
+                    .ok_or_else(|| "invalid array data".to_string())?;
+                let items = items
+                    .iter()
+                    .map(DbValue::to_display_string)
+                    .collect::<Result<Vec<String>, String>>()?;
+                Ok(format!("[{}]", items.join(", ")))
+            }
+            DataType::Hash => {
+                let fields = self
+                    .hash_fields()
+                    .ok_or_else(|| "invalid hash data".to_string())?;
+                let fields = fields
+                    .iter()
+                    .map(|(field, value)| format!("{}: {}", field, value))
+                    .collect::<Vec<String>>();
+                Ok(format!("{{{}}}", fields.join(", ")))
+            }
+        }
+    }
+}
+
+// Renders the decoded value, as shown by GET.
+// Values whose data doesn't fit their typetag show up as `<reason>`.
+impl fmt::Display for DbValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_display_string() {
+            Ok(s) => write!(f, "{}", s),
+            Err(e) => write!(f, "<{}>", e),
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Database {
+    db: HashMap<String, DbValue>,
+    // Set by every mutation and cleared by a successful save.
+    dirty: bool,
+    // Counts mutations. Unlike `dirty` a save doesn't reset it, so a writer
+    // like Autosave can tell whether anything changed since its own last
+    // write, whatever other files were saved in between.
+    generation: u64,
+    // Write-ahead log that mutating commands are appended to, if enabled.
+    wal: Option<String>,
+    // Keys from oldest to most recently inserted. An overwrite counts as a
+    // new insertion.
+    order: Vec<String>,
+    // Keys from least to most recently used, by GET or SET. With `max_keys`
+    // set, the front is evicted to make room for a new key.
+    access: Vec<String>,
+    max_keys: Option<usize>,
+    // Fold keys to lowercase so `Foo` and `foo` name the same entry.
+    case_insensitive: bool,
+    // Refuse every mutating command.
+    readonly: bool,
+    // Refuse SETs that would change the type of an existing key.
+    strict_types: bool,
+    // Largest encoded value a write may leave behind.
+    max_value_bytes: Option<usize>,
+    // Clients to notify when a key changes, by database index and key.
+    watchers: HashMap<(usize, String), Vec<ClientId>>,
+    // What each connected client has set up for itself.
+    sessions: HashMap<ClientId, Session>,
+    // The client whose command is running, as set by the front end.
+    client: ClientId,
+    // When `save` last succeeded.
+    last_save: Option<SystemTime>,
+    // Picks RANDOMKEY's key.
+    rng: Rng,
+    // Only the selected database lives in `db`, `order` and `access`. The
+    // others wait in `shelf`, which stays empty until the first SELECT;
+    // `shelf[selected]` is an empty placeholder.
+    selected: usize,
+    shelf: Vec<Database>,
+    // The database the WAL's last line applies to.
+    wal_db: usize,
+    // Decides when values expire.
+    clock: Clock,
+}
+
+// One client's state, as opposed to the data all clients share. RESET
+// puts it back to the default. The client's watches are in `watchers`,
+// listed under each key.
+#[derive(Default, Clone)]
+struct Session {
+    // Where WATCH notifications go, if the front end subscribed the client.
+    sender: Option<Sender<String>>,
+    // The database SELECTed, put back by `set_client`.
+    selected: usize,
+    // Open BEGIN block, if any.
+    transaction: Option<Transaction>,
+}
+
+// What a ROLLBACK restores, plus the WAL writes held back until COMMIT.
+#[derive(Clone)]
+struct Transaction {
+    snapshot: Box<Database>,
+    wal: Vec<String>,
+    // LOAD or IMPORT ran, so COMMIT rewrites the whole log instead.
+    compact: bool,
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Database::default()
+    }
+
+    pub fn last_save(&self) -> Option<SystemTime> {
+        self.last_save
+    }
+
+    // Uncommitted changes are never worth saving.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty && self.open_transaction().is_none()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Whether there are committed changes since `generation` was read.
+    pub fn changed_since(&self, generation: u64) -> bool {
+        self.generation != generation && self.open_transaction().is_none()
+    }
+
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+        self.generation += 1;
+    }
+
+    pub fn set_case_insensitive(&mut self, on: bool) {
+        self.case_insensitive = on;
+    }
+
+    pub fn set_readonly(&mut self, on: bool) {
+        self.readonly = on;
+    }
+
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    pub fn set_max_keys(&mut self, max: Option<usize>) {
+        self.max_keys = max;
+    }
+
+    pub fn set_max_value_bytes(&mut self, max: Option<usize>) {
+        self.max_value_bytes = max;
+    }
+
+    // Checked against the encoded data, so it holds for every type.
+    pub fn check_size(&self, value: &DbValue) -> Result<(), String> {
+        value_fits(self.max_value_bytes, value.data.len())
+    }
+
+    pub fn set_strict_types(&mut self, on: bool) {
+        self.strict_types = on;
+    }
+
+    pub fn is_strict_types(&self) -> bool {
+        self.strict_types
+    }
+
+    // With strict types on, a key keeps its type until it is deleted.
+    pub fn check_type(&self, key: &str, value: &DbValue) -> Result<(), String> {
+        if !self.strict_types {
+            return Ok(());
+        }
+
+        let key = self.normalize_key(key);
+        match self.db.get(key.as_ref()) {
+            Some(existing)
+                if !existing.is_expired(self.now()) && existing.typetag != value.typetag =>
+            {
+                Err(format!(
+                    "Type mismatch: {} holds {}, not {} (DELETE it first)",
+                    key,
+                    existing.type_name(),
+                    value.type_name()
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Every key a caller passes in goes through here first.
+    pub fn normalize_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if self.case_insensitive {
+            Cow::Owned(key.to_lowercase())
+        } else {
+            Cow::Borrowed(key)
+        }
+    }
+
+    // Takes over the contents of a loaded database, keeping this one's WAL
+    // and key settings. Values over --max-value-bytes are left out; the
+    // returned list explains each one. Watchers hear about the keys that
+    // changed or went, not about every key read in.
+    pub fn replace(&mut self, mut loaded: Database) -> Vec<String> {
+        let mut skipped = Vec::new();
+        let selected = self.selected;
+        let count = if self.shelf.is_empty() && loaded.shelf.is_empty() {
+            1
+        } else {
+            DATABASES
+        };
+
+        for index in 0..count {
+            self.switch_to(index);
+            loaded.switch_to(index);
+            let old = mem::take(&mut self.db);
+            self.order.clear();
+            self.access.clear();
+            for key in mem::take(&mut loaded.order) {
+                let Some(value) = loaded.db.remove(&key) else {
+                    continue;
+                };
+                if value.is_expired(self.now()) {
+                    continue;
+                }
+                match self.check_size(&value) {
+                    Ok(()) => {
+                        let key = self.normalize_key(&key).into_owned();
+                        self.store(key, value);
+                    }
+                    Err(e) => skipped.push(format!("{}: {}", key, e)),
+                }
+            }
+            self.notify_replaced(index, &old);
+        }
+        self.switch_to(selected);
+        self.mark_dirty();
+        skipped
+    }
+
+    // Tells watchers of database `index` how its contents differ from `old`.
+    fn notify_replaced(&mut self, index: usize, old: &HashMap<String, DbValue>) {
+        let now = self.now();
+        let live = |value: &&DbValue| !value.is_expired(now);
+        let events = self
+            .watchers
+            .keys()
+            .filter(|(watched, _key)| *watched == index)
+            .filter_map(|(_index, key)| {
+                match (old.get(key).filter(live), self.db.get(key).filter(live)) {
+                    (Some(before), Some(after)) if before.same_value(after) => None,
+                    (_, Some(_)) => Some((key.clone(), "set")),
+                    (Some(_), None) => Some((key.clone(), "deleted")),
+                    (None, None) => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        for (key, event) in events {
+            self.notify_in(index, &key, event);
+        }
+    }
+
+    // Every insert and removal goes through these, so `order` and `access`
+    // stay in step with `db`.
+
+    fn insert(&mut self, key: String, value: DbValue) -> Option<DbValue> {
+        let key = self.normalize_key(&key).into_owned();
+        let replaced = self.store(key.clone(), value);
+        self.notify(&key, "set");
+        replaced
+    }
+
+    // `insert` without the notification. The key must already be
+    // normalized.
+    fn store(&mut self, key: String, value: DbValue) -> Option<DbValue> {
+        if let Some(max) = self.max_keys
+            && !self.db.contains_key(&key)
+        {
+            while self.db.len() >= max && !self.access.is_empty() {
+                let lru = self.access[0].clone();
+                self.remove(&lru);
+            }
+        }
+
+        self.order.retain(|k| *k != key);
+        self.order.push(key.clone());
+        self.touch_access(&key);
+        self.mark_dirty();
+        self.db.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &str) -> Option<DbValue> {
+        let removed = self.db.remove(key);
+        if removed.is_some() {
+            self.order.retain(|k| k != key);
+            self.access.retain(|k| k != key);
+            self.notify(key, "deleted");
+        }
+        removed
+    }
+
+    // Drops `key` if it has expired, so callers only ever see live values.
+    fn purge_expired(&mut self, key: &str) {
+        let now = self.now();
+        if self.db.get(key).is_some_and(|value| value.is_expired(now)) {
+            self.remove(key);
+        }
+    }
+
+    // Moves `key` to the most recently used end of `access`.
+    fn touch_access(&mut self, key: &str) {
+        self.access.retain(|k| k != key);
+        self.access.push(key.to_string());
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<&DbValue> {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        if self.db.contains_key(key.as_ref()) {
+            self.touch_access(&key);
+        }
+        self.db.get(key.as_ref())
+    }
+
+    // Results are in the same order as `keys`.
+    pub fn mget(&self, keys: &[&str]) -> Vec<Option<&DbValue>> {
+        let now = self.now();
+        keys.iter()
+            .map(|key| {
+                self.db
+                    .get(self.normalize_key(key).as_ref())
+                    .filter(|value| !value.is_expired(now))
+            })
+            .collect()
+    }
+
+    pub fn set(&mut self, key: String, value: DbValue) {
+        self.insert(key, value);
+    }
+
+    // Like set, but hands back the value that was replaced.
+    pub fn getset(&mut self, key: String, value: DbValue) -> Option<DbValue> {
+        self.purge_expired(&self.normalize_key(&key));
+        self.insert(key, value)
+    }
+
+    // Returns false, leaving the current value alone, if the key exists.
+    pub fn set_nx(&mut self, key: String, value: DbValue) -> bool {
+        if self.exists(&key) {
+            return false;
+        }
+        self.set(key, value);
+        true
+    }
+
+    // Stores `new` only if the current value is `expected`. Returns false,
+    // changing nothing, on a mismatch or a missing key.
+    pub fn compare_and_set(&mut self, key: &str, expected: &DbValue, new: DbValue) -> bool {
+        let matches = self
+            .get(key)
+            .is_some_and(|current| current.same_value(expected));
+        if matches {
+            self.set(key.to_string(), new);
+        }
+        matches
+    }
+
+    pub fn delete(&mut self, key: &str) -> Option<DbValue> {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        let removed = self.remove(&key);
+        if removed.is_some() {
+            self.mark_dirty();
+        }
+        removed
+    }
+
+    // Removes every key matching a SCAN pattern and returns how many went.
+    pub fn delete_matching(&mut self, pattern: &str) -> usize {
+        // Collected first, since removing while iterating isn't allowed.
+        let keys = self
+            .scan(pattern)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<String>>();
+        for key in &keys {
+            self.remove(key);
+        }
+        if !keys.is_empty() {
+            self.mark_dirty();
+        }
+        keys.len()
+    }
+
+    // Returns false if the key doesn't exist.
+    pub fn expire(&mut self, key: &str, after: Duration) -> Result<bool, String> {
+        let at = self
+            .now()
+            .checked_add(after)
+            .ok_or("Expiry is too far in the future")?;
+        Ok(self.expire_at(key, at, Some(after)))
+    }
+
+    // Sets the moment the key goes, and the window TOUCH restarts, if any.
+    // Returns false if the key doesn't exist.
+    pub fn expire_at(&mut self, key: &str, at: SystemTime, window: Option<Duration>) -> bool {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        match self.db.get_mut(key.as_ref()) {
+            Some(value) => {
+                value.expires_at = Some(at);
+                value.ttl = window;
+                self.mark_dirty();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The window EXPIRE gave a live key, for TOUCH to restart.
+    pub fn expiry_window(&self, key: &str) -> Option<Duration> {
+        self.db
+            .get(self.normalize_key(key).as_ref())
+            .filter(|value| !value.is_expired(self.now()))
+            .and_then(|value| value.ttl)
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        for db in &mut self.shelf {
+            db.clock = clock.clone();
+        }
+        self.clock = clock;
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    // Marks a key as just used and restarts its expiry window, if it has
+    // one. Returns false if the key doesn't exist.
+    pub fn touch(&mut self, key: &str) -> bool {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        let now = self.now();
+        let Some(value) = self.db.get_mut(key.as_ref()) else {
+            return false;
+        };
+
+        if let Some(at) = value.ttl.and_then(|ttl| now.checked_add(ttl)) {
+            value.expires_at = Some(at);
+            self.mark_dirty();
+        }
+        self.touch_access(&key);
+        true
+    }
+
+    // Makes a key permanent again. Returns false if it had no expiry.
+    pub fn persist(&mut self, key: &str) -> bool {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        let Some(value) = self.db.get_mut(key.as_ref()) else {
+            return false;
+        };
+        if value.expires_at.is_none() {
+            return false;
+        }
+
+        value.expires_at = None;
+        value.ttl = None;
+        self.mark_dirty();
+        true
+    }
+
+    // Remaining seconds, -1 for a key without expiry and -2 for a missing key.
+    pub fn ttl(&mut self, key: &str) -> i64 {
+        let now = self.now();
+        match self.get(key) {
+            Some(value) => match value.expires_at {
+                Some(at) => at
+                    .duration_since(now)
+                    .unwrap_or_default()
+                    .as_secs_f64()
+                    .ceil() as i64,
+                None => -1,
+            },
+            None => -2,
+        }
+    }
+
+    // Overwrites `new` if it already exists, unless strict types would
+    // refuse the overwrite.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<(), String> {
+        let old = self.normalize_key(old);
+        self.purge_expired(&old);
+        let value = self.db.get(old.as_ref()).ok_or("Key not found")?;
+        if old != self.normalize_key(new) {
+            self.check_type(new, value)?;
+        }
+        let value = self.remove(&old).ok_or("Key not found")?;
+        self.set(new.to_string(), value);
+        Ok(())
+    }
+
+    // Exchanges two values, expiries included. Both keys must exist, so a
+    // failed swap changes nothing.
+    pub fn swap(&mut self, a: &str, b: &str) -> Result<(), String> {
+        let a = self.normalize_key(a).into_owned();
+        let b = self.normalize_key(b).into_owned();
+        for key in [&a, &b] {
+            if !self.exists(key) {
+                return Err(format!("Key not found: {}", key));
+            }
+        }
+        if a == b {
+            return Ok(());
+        }
+        self.check_type(&a, &self.db[&b])?;
+        self.check_type(&b, &self.db[&a])?;
+
+        // Both are checked above, so neither remove can come back empty.
+        let value_a = self.db.remove(&a).unwrap();
+        let value_b = self.db.remove(&b).unwrap();
+        self.db.insert(a.clone(), value_b);
+        self.db.insert(b.clone(), value_a);
+        self.mark_dirty();
+        self.notify(&a, "set");
+        self.notify(&b, "set");
+        Ok(())
+    }
+
+    // Refuses to overwrite `dst` unless `replace` is set, and with strict
+    // types, unless it holds the same type.
+    pub fn copy(&mut self, src: &str, dst: &str, replace: bool) -> Result<(), String> {
+        let value = self.get(src).ok_or("Key not found")?.clone();
+        if !replace && self.exists(dst) {
+            return Err(format!("{} already exists (use REPLACE to overwrite)", dst));
+        }
+        self.check_type(dst, &value)?;
+        self.set(dst.to_string(), value);
+        Ok(())
+    }
+
+    // Missing keys start from 0, so the first INCR stores 1.
+    pub fn adjust_int(&mut self, key: &str, delta: i64) -> Result<i64, String> {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        match self.db.get_mut(key.as_ref()) {
+            Some(value) => {
+                let current = value.as_int().ok_or("Value is not an integer")?;
+                let new = current
+                    .checked_add(delta)
+                    .ok_or("Result would overflow an integer")?;
+                // Updated in place so an expiry set on the key survives.
+                value.data = new.to_be_bytes().to_vec();
+                self.mark_dirty();
+                self.notify(&key, "set");
+                Ok(new)
+            }
+            None => {
+                self.set(key.to_string(), DbValue::from_i64(delta));
+                Ok(delta)
+            }
+        }
+    }
+
+    // Returns the new length in bytes.
+    pub fn append(&mut self, key: &str, text: &str) -> Result<usize, String> {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        let max = self.max_value_bytes;
+        match self.db.get_mut(key.as_ref()) {
+            Some(value) if value.typetag != DataType::String => {
+                Err("Value is not a string".to_string())
+            }
+            Some(value) => {
+                value_fits(max, value.data.len() + text.len())?;
+                value.data.extend_from_slice(text.as_bytes());
+                let len = value.data.len();
+                self.mark_dirty();
+                self.notify(&key, "set");
+                Ok(len)
+            }
+            None => {
+                value_fits(max, text.len())?;
+                self.set(key.to_string(), DbValue::from_str(text));
+                Ok(text.len())
+            }
+        }
+    }
+
+    // Writes `text` over a string starting at byte `offset`, padding with
+    // spaces past the end. A missing key starts out empty. Returns the new
+    // length.
+    pub fn setrange(&mut self, key: &str, offset: usize, text: &str) -> Result<usize, String> {
+        let end = offset
+            .checked_add(text.len())
+            .filter(|end| *end <= MAX_SETRANGE_LEN)
+            .ok_or_else(|| format!("String would exceed {}", human_size(MAX_SETRANGE_LEN)))?;
+
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        let max = self.max_value_bytes;
+        let Some(value) = self.db.get_mut(key.as_ref()) else {
+            value_fits(max, end)?;
+            let padded = format!("{}{}", " ".repeat(offset), text);
+            self.set(key.into_owned(), DbValue::from_str(&padded));
+            return Ok(end);
+        };
+        if value.typetag != DataType::String {
+            return Err("Value is not a string".to_string());
+        }
+
+        // Overwriting half of a character would leave invalid UTF-8.
+        if let Ok(existing) = str::from_utf8(&value.data)
+            && (!existing.is_char_boundary(offset.min(existing.len()))
+                || !existing.is_char_boundary(end.min(existing.len())))
+        {
+            return Err("Range splits a UTF-8 character".to_string());
+        }
+
+        value_fits(max, end.max(value.data.len()))?;
+        if value.data.len() < end {
+            value.data.resize(end, b' ');
+        }
+        value.data[offset..end].copy_from_slice(text.as_bytes());
+        let len = value.data.len();
+        self.mark_dirty();
+        self.notify(&key, "set");
+        Ok(len)
+    }
+
+    // Cuts a string to at most `len` bytes, backing off to a char boundary
+    // so the result stays valid UTF-8. Returns the new length.
+    pub fn truncate(&mut self, key: &str, len: usize) -> Result<usize, String> {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        let value = self.db.get_mut(key.as_ref()).ok_or("Key not found")?;
+        if value.typetag != DataType::String {
+            return Err("Value is not a string".to_string());
+        }
+
+        let mut end = len.min(value.data.len());
+        if let Ok(text) = str::from_utf8(&value.data) {
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+        }
+        if end < value.data.len() {
+            value.data.truncate(end);
+            self.mark_dirty();
+            self.notify(&key, "set");
+        }
+        Ok(end)
+    }
+
+    // Bytes `start..=end` of a string. Negative indices count from the end
+    // and out-of-range ones are clamped, but the range must not split a
+    // character.
+    pub fn getrange(&mut self, key: &str, start: i64, end: i64) -> Result<String, String> {
+        let value = self.get(key).ok_or("Key not found")?;
+        if value.typetag != DataType::String {
+            return Err("Value is not a string".to_string());
+        }
+        let text = str::from_utf8(&value.data).map_err(|_| "String data is not valid UTF-8")?;
+
+        let len = text.len() as i64;
+        let resolve = |i: i64| if i < 0 { len + i } else { i };
+        let start = resolve(start).max(0);
+        let end = resolve(end).min(len - 1);
+        if start > end {
+            return Ok(String::new());
+        }
+        text.get(start as usize..=end as usize)
+            .map(str::to_string)
+            .ok_or_else(|| "Range splits a UTF-8 character".to_string())
+    }
+
+    // Adds `item` to the front or back of an array, creating it if the key
+    // is missing. Returns the new length.
+    pub fn push(&mut self, key: &str, item: DbValue, front: bool) -> Result<usize, String> {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        let max = self.max_value_bytes;
+        let Some(value) = self.db.get_mut(key.as_ref()) else {
+            let array = DbValue::from_array(&[item]);
+            value_fits(max, array.data.len())?;
+            self.set(key.into_owned(), array);
+            return Ok(1);
+        };
+
+        let (item_type, mut items) = match value.array_items() {
+            Some(array) => array,
+            None if value.typetag == DataType::Array => {
+                return Err("Array data is corrupt".to_string());
+            }
+            None => return Err("Value is not an array".to_string()),
+        };
+        if item.typetag != item_type {
+            return Err(format!("Array holds {} values", item_type.name()));
+        }
+
+        if front {
+            items.insert(0, item);
+        } else {
+            items.push(item);
+        }
+        // Rebuilt in place so an expiry set on the key survives.
+        let data = DbValue::from_array(&items).data;
+        value_fits(max, data.len())?;
+        value.data = data;
+        self.mark_dirty();
+        self.notify(&key, "set");
+        Ok(items.len())
+    }
+
+    // Sets one field of a hash, creating the hash if the key is missing.
+    // Returns true if the field is new.
+    pub fn hset(&mut self, key: &str, field: &str, text: &str) -> Result<bool, String> {
+        let key = self.normalize_key(key);
+        self.purge_expired(&key);
+        let max = self.max_value_bytes;
+        let Some(value) = self.db.get_mut(key.as_ref()) else {
+            let hash = DbValue::from_hash(&[(field.to_string(), text.to_string())]);
+            value_fits(max, hash.data.len())?;
+            self.set(key.into_owned(), hash);
+            return Ok(true);
+        };
+
+        let mut fields = match value.hash_fields() {
+            Some(fields) => fields,
+            None if value.typetag == DataType::Hash => {
+                return Err("Hash data is corrupt".to_string());
+            }
+            None => return Err("Value is not a hash".to_string()),
+        };
+        let added = match fields.iter_mut().find(|(f, _)| f == field) {
+            Some((_, existing)) => {
+                *existing = text.to_string();
+                false
+            }
+            None => {
+                fields.push((field.to_string(), text.to_string()));
+                true
+            }
+        };
+
+        // Rebuilt in place so an expiry set on the key survives.
+        let data = DbValue::from_hash(&fields).data;
+        value_fits(max, data.len())?;
+        value.data = data;
+        self.mark_dirty();
+        self.notify(&key, "set");
+        Ok(added)
+    }
+
+    // Every field of a hash, in insertion order.
+    pub fn hgetall(&mut self, key: &str) -> Result<Vec<(String, String)>, String> {
+        let value = self.get(key).ok_or("Key not found")?;
+        if value.typetag != DataType::Hash {
+            return Err("Value is not a hash".to_string());
+        }
+        value
+            .hash_fields()
+            .ok_or_else(|| "Hash data is corrupt".to_string())
+    }
+
+    pub fn hget(&mut self, key: &str, field: &str) -> Result<String, String> {
+        self.hgetall(key)?
+            .into_iter()
+            .find(|(f, _)| f == field)
+            .map(|(_, value)| value)
+            .ok_or_else(|| "Field not found".to_string())
+    }
+
+    // Items `start..=stop` of an array. Negative indices count from the end
+    // (-1 is the last item) and out-of-range indices are clamped.
+    pub fn lrange(&mut self, key: &str, start: i64, stop: i64) -> Result<Vec<DbValue>, String> {
+        let value = self.get(key).ok_or("Key not found")?;
+        if value.typetag != DataType::Array {
+            return Err("Value is not an array".to_string());
+        }
+        let (_, items) = value.array_items().ok_or("Array data is corrupt")?;
+
+        let len = items.len() as i64;
+        let resolve = |i: i64| if i < 0 { len + i } else { i };
+        let start = resolve(start).max(0);
+        let stop = resolve(stop).min(len - 1);
+        if start > stop {
+            return Ok(Vec::new());
+        }
+        Ok(items[start as usize..=stop as usize].to_vec())
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.db
+            .get(self.normalize_key(key).as_ref())
+            .is_some_and(|value| !value.is_expired(self.now()))
+    }
+
+    pub fn clear(&mut self) {
+        let watched = self
+            .watchers
+            .keys()
+            .filter(|(index, key)| *index == self.selected && self.db.contains_key(key))
+            .map(|(_index, key)| key.clone())
+            .collect::<Vec<String>>();
+        for key in watched {
+            self.notify(&key, "deleted");
+        }
+        self.db.clear();
+        self.order.clear();
+        self.access.clear();
+        self.mark_dirty();
+    }
+
+    // CLEAR for every database. Returns how many keys went.
+    pub fn clear_all(&mut self) -> usize {
+        let removed = self.total_count();
+        if self.shelf.is_empty() {
+            self.clear();
+            return removed;
+        }
+        let selected = self.selected;
+        for index in 0..DATABASES {
+            self.switch_to(index);
+            self.clear();
+        }
+        self.switch_to(selected);
+        removed
+    }
+
+    // Rough bytes held by live entries: key and value bytes, the copy of the
+    // key in `order`, and the fixed size of each map slot.
+    pub fn estimated_size(&self) -> usize {
+        let overhead = mem::size_of::<String>() * 2 + mem::size_of::<DbValue>();
+        self.iter()
+            .map(|(key, value)| key.len() * 2 + value.data.len() + overhead)
+            .sum()
+    }
+
+    // (count, data bytes) per type name, for the types that are present.
+    pub fn stats(&self) -> HashMap<&'static str, (usize, usize)> {
+        let mut stats = HashMap::new();
+        for (_key, value) in self.iter() {
+            let (count, bytes) = stats.entry(value.type_name()).or_insert((0, 0));
+            *count += 1;
+            *bytes += value.data.len();
+        }
+        stats
+    }
+
+    pub fn count(&self) -> usize {
+        self.iter().count()
+    }
+
+    // Live keys in every database, as SAVE and LOAD see them.
+    pub fn total_count(&self) -> usize {
+        self.databases().iter().map(|(_, db)| db.count()).sum()
+    }
+
+    // Skips expired entries that haven't been purged yet.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &DbValue)> {
+        let now = self.now();
+        self.db
+            .iter()
+            .filter(move |(_key, value)| !value.is_expired(now))
+    }
+
+    pub fn keys(&self) -> Vec<&String> {
+        let mut keys = self
+            .iter()
+            .map(|(key, _value)| key)
+            .collect::<Vec<&String>>();
+        keys.sort();
+        keys
+    }
+
+    // Any live key, each equally likely.
+    pub fn random_key(&mut self) -> Option<String> {
+        let len = self.keys().len();
+        if len == 0 {
+            return None;
+        }
+        // Picked from the sorted keys so a seeded generator is repeatable.
+        let i = self.rng.below(len);
+        Some(self.keys()[i].clone())
+    }
+
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    // The last `n` inserted keys, most recent first.
+    pub fn recent(&self, n: usize) -> Vec<&String> {
+        self.order
+            .iter()
+            .rev()
+            .filter(|key| self.exists(key))
+            .take(n)
+            .collect()
+    }
+
+    // Sorted keys matching a glob pattern (`*` and `?`).
+    pub fn scan(&self, pattern: &str) -> Vec<&String> {
+        let pattern = self.normalize_key(pattern);
+        let mut keys = self.keys();
+        keys.retain(|key| glob_match(&pattern, key));
+        keys
+    }
+
+    pub fn keys_of_type(&self, value_type: &DataType) -> Vec<&String> {
+        let mut keys = self.keys();
+        keys.retain(|key| self.db[*key].typetag == *value_type);
+        keys
+    }
+
+    // Int and float values under keys matching `pattern`, ordered by value.
+    // Equal values stay in key order, descending or not.
+    pub fn sort_numeric(&self, pattern: &str, descending: bool) -> Vec<(&String, &DbValue)> {
+        let mut entries = self
+            .scan(pattern)
+            .into_iter()
+            .filter_map(|key| {
+                let value = &self.db[key];
+                let number = value.as_int().map(|i| i as f64).or(value.as_float())?;
+                Some((number, key, value))
+            })
+            .collect::<Vec<(f64, &String, &DbValue)>>();
+
+        entries.sort_by(|a, b| {
+            let order = a.0.total_cmp(&b.0);
+            if descending { order.reverse() } else { order }
+        });
+        entries
+            .into_iter()
+            .map(|(_number, key, value)| (key, value))
+            .collect()
+    }
+
+    // Sorted keys whose string value contains `needle`, comparing ASCII
+    // letters without case if `ignore_case` is set. Other types, and strings
+    // that aren't valid UTF-8, never match.
+    pub fn find_value(&self, needle: &str, ignore_case: bool) -> Vec<&String> {
+        // Only copies are lowercased; stored values are left as they are.
+        let needle = if ignore_case {
+            Cow::Owned(needle.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(needle)
+        };
+        let matches = |s: &str| {
+            if ignore_case {
+                s.to_ascii_lowercase().contains(needle.as_ref())
+            } else {
+                s.contains(needle.as_ref())
+            }
+        };
+
+        let mut keys = self
+            .iter()
+            .filter(|(_key, value)| value.as_string().is_some_and(matches))
+            .map(|(key, _value)| key)
+            .collect::<Vec<&String>>();
+        keys.sort();
+        keys
+    }
+
+    // Keys whose int value lies in `min..=max`, ordered by value, then key.
+    // Other types are skipped.
+    pub fn range(&self, min: i64, max: i64) -> Vec<&String> {
+        let mut hits = self
+            .iter()
+            .filter(|(_key, value)| value.typetag == DataType::Int)
+            .filter_map(|(key, value)| value.as_int().map(|i| (i, key)))
+            .filter(|(i, _key)| *i >= min && *i <= max)
+            .collect::<Vec<(i64, &String)>>();
+        hits.sort();
+        hits.into_iter().map(|(_i, key)| key).collect()
+    }
+
+    // == Disk format ==
+    // MAGIC, FORMAT_VERSION, then for each entry:
+    // [key len: u32][key bytes][typetag: u8][data len: u32][data bytes]
+    // [expires at: u64][window: u64]
+    // The expiry is in milliseconds since the Unix epoch, and the window is
+    // the one TOUCH restarts, in milliseconds. 0 means none for both.
+    // Lengths, and the ints, uints, floats and datetimes in the data, are
+    // big-endian, so files move between machines. Files written with native
+    // byte order by older builds are not supported.
+    // Entries start out in database 0. A zero key length, which no real key
+    // has, is followed by a database index byte instead, and the entries
+    // after it belong to that database.
+    // Files from before the version byte are read as version 0: their next
+    // byte is the high byte of the first key length, which is always 0.
+
+    pub fn save(&mut self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&[FORMAT_VERSION])?;
+
+        for (index, db) in self.databases() {
+            if index > 0 && db.iter().next().is_some() {
+                file.write_all(&0u32.to_be_bytes())?;
+                file.write_all(&[index as u8])?;
+            }
+            for (key, value) in db.iter() {
+                file.write_all(&(key.len() as u32).to_be_bytes())?;
+                file.write_all(key.as_bytes())?;
+                file.write_all(&[value.typetag.clone() as u8])?;
+                file.write_all(&(value.data.len() as u32).to_be_bytes())?;
+                file.write_all(&value.data)?;
+                let expires_at = value.expires_at.map_or(0, clock::to_millis);
+                let window = value.ttl.map_or(0, |ttl| ttl.as_millis() as u64);
+                file.write_all(&expires_at.to_be_bytes())?;
+                file.write_all(&window.to_be_bytes())?;
+            }
+        }
+
+        file.flush()?;
+        self.dirty = false;
+        self.last_save = Some(SystemTime::now());
+        Ok(())
+    }
+
+    // == Numbered databases ==
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn select(&mut self, index: usize) -> Result<(), String> {
+        check_database(index)?;
+        self.switch_to(index);
+        self.session_mut().selected = index;
+        Ok(())
+    }
+
+    fn switch_to(&mut self, index: usize) {
+        if index == self.selected {
+            return;
+        }
+        if self.shelf.is_empty() {
+            self.shelf = self.empty_shelf();
+        }
+        // Park the current data in its slot, then take the new one's.
+        self.swap_with_shelf(self.selected);
+        self.swap_with_shelf(index);
+        self.selected = index;
+    }
+
+    // Shelved databases read the same clock, so they agree on what has
+    // expired.
+    fn empty_shelf(&self) -> Vec<Database> {
+        let empty = Database {
+            clock: self.clock.clone(),
+            ..Database::default()
+        };
+        vec![empty; DATABASES]
+    }
+
+    fn swap_with_shelf(&mut self, index: usize) {
+        let other = &mut self.shelf[index];
+        mem::swap(&mut self.db, &mut other.db);
+        mem::swap(&mut self.order, &mut other.order);
+        mem::swap(&mut self.access, &mut other.access);
+    }
+
+    // Exchanges the contents of two databases. Clients stay on the index
+    // they selected, so they see the other contents from now on.
+    pub fn swap_databases(&mut self, a: usize, b: usize) -> Result<(), String> {
+        check_database(a)?;
+        check_database(b)?;
+        if self.shelf.is_empty() {
+            self.shelf = self.empty_shelf();
+        }
+
+        // With the selected data parked, every database is in `shelf`.
+        self.swap_with_shelf(self.selected);
+        self.shelf.swap(a, b);
+        self.swap_with_shelf(self.selected);
+        self.mark_dirty();
+        Ok(())
+    }
+
+    // Every database with its index, the selected one included.
+    fn databases(&self) -> Vec<(usize, &Database)> {
+        if self.shelf.is_empty() {
+            return vec![(0, self)];
+        }
+        (0..DATABASES)
+            .map(|i| {
+                (
+                    i,
+                    if i == self.selected {
+                        self
+                    } else {
+                        &self.shelf[i]
+                    },
+                )
+            })
+            .collect()
+    }
+
+    // Moves a key to another database. Returns false if the key is missing
+    // here or already exists there.
+    pub fn move_key(&mut self, key: &str, index: usize) -> Result<bool, String> {
+        check_database(index)?;
+        if index == self.selected {
+            return Err("Source and destination databases are the same".to_string());
+        }
+
+        let key = self.normalize_key(key).into_owned();
+        if !self.exists(&key) {
+            return Ok(false);
+        }
+        if self.shelf.is_empty() {
+            self.shelf = self.empty_shelf();
+        }
+        if self.shelf[index].exists(&key) {
+            return Ok(false);
+        }
+
+        let value = self.remove(&key).ok_or("Key not found")?;
+        self.shelf[index].insert(key.clone(), value);
+        self.notify_in(index, &key, "set");
+        self.mark_dirty();
+        Ok(true)
+    }
+
+    // == Transactions ==
+    // BEGIN snapshots the database; ROLLBACK puts the snapshot back and
+    // COMMIT drops it and writes the held-back WAL lines. Only one client
+    // can have a transaction open, and every other client's writes wait
+    // for it to end, since a ROLLBACK would otherwise erase them too.
+
+    // Whether the current client has a transaction open.
+    pub fn in_transaction(&self) -> bool {
+        self.sessions
+            .get(&self.client)
+            .is_some_and(|session| session.transaction.is_some())
+    }
+
+    // The open transaction and the client that began it.
+    fn open_transaction(&self) -> Option<(ClientId, &Transaction)> {
+        self.sessions
+            .iter()
+            .find_map(|(client, session)| Some((*client, session.transaction.as_ref()?)))
+    }
+
+    // Refuses a write while another client's transaction is open.
+    pub fn check_transaction_lock(&self) -> Result<(), String> {
+        match self.open_transaction() {
+            Some((owner, _)) if owner != self.client => Err(format!(
+                "Client {} has a transaction open; try again once it ends",
+                owner
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn begin(&mut self) -> Result<(), String> {
+        if self.in_transaction() {
+            return Err("Already in a transaction".to_string());
+        }
+        self.check_transaction_lock()?;
+
+        // The snapshot is of the data only; sessions are left out of it.
+        let sessions = mem::take(&mut self.sessions);
+        let snapshot = Box::new(self.clone());
+        self.sessions = sessions;
+        self.session_mut().transaction = Some(Transaction {
+            snapshot,
+            wal: Vec::new(),
+            compact: false,
+        });
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> Result<(), String> {
+        let tx = self
+            .session_mut()
+            .transaction
+            .take()
+            .ok_or("No transaction in progress")?;
+
+        let written = if tx.compact {
+            self.compact_wal()
+        } else {
+            tx.wal.iter().try_for_each(|line| self.append_wal(line))
+        };
+        written.map_err(|e| format!("Failed to write WAL: {}", e))
+    }
+
+    pub fn rollback(&mut self) -> Result<(), String> {
+        let tx = self
+            .session_mut()
+            .transaction
+            .take()
+            .ok_or("No transaction in progress")?;
+        self.restore_snapshot(*tx.snapshot);
+        Ok(())
+    }
+
+    // Puts the data back as it was at BEGIN. Sessions and watches belong to
+    // clients, not to the data, so they outlive the rollback.
+    fn restore_snapshot(&mut self, mut snapshot: Database) {
+        let client = self.client;
+        snapshot.watchers = mem::take(&mut self.watchers);
+        snapshot.sessions = mem::take(&mut self.sessions);
+        snapshot.last_save = self.last_save;
+        // The data changed back, and a generation already used since BEGIN
+        // must not come around again.
+        snapshot.generation = self.generation + 1;
+        *self = snapshot;
+        self.set_client(client);
+    }
+
+    // == Sessions ==
+    // The front end calls `set_client` before each command, so the
+    // methods here act for whoever sent it.
+
+    // Also brings back the database the client last selected.
+    pub fn set_client(&mut self, client: ClientId) {
+        self.client = client;
+        let selected = self.sessions.get(&client).map_or(0, |s| s.selected);
+        self.switch_to(selected);
+    }
+
+    fn session_mut(&mut self) -> &mut Session {
+        self.sessions.entry(self.client).or_default()
+    }
+
+    // Puts the current client back in a fresh session: its open transaction
+    // is rolled back, its watches are dropped and database 0 is selected.
+    // Stored data, the notification channel and other clients' sessions are
+    // kept.
+    pub fn reset_session(&mut self) {
+        if self.in_transaction() {
+            let _ = self.rollback();
+        }
+        self.drop_watches(self.client);
+        let sender = self.session_mut().sender.take();
+        *self.session_mut() = Session {
+            sender,
+            ..Session::default()
+        };
+        self.switch_to(0);
+    }
+
+    // == Watches ==
+    // A client registers a channel with `subscribe`, then WATCHes keys. Every
+    // set or delete of a watched key sends it a `WATCH <key> <event>` line.
+
+    pub fn subscribe(&mut self, client: ClientId, sender: Sender<String>) {
+        self.sessions.entry(client).or_default().sender = Some(sender);
+    }
+
+    // Ends the client's session: rolls back its transaction if it left one
+    // open, and drops its channel and all of its watches.
+    pub fn unsubscribe(&mut self, client: ClientId) {
+        let current = self.client;
+        self.set_client(client);
+        if self.in_transaction() {
+            let _ = self.rollback();
+        }
+        self.sessions.remove(&client);
+        self.drop_watches(client);
+        self.set_client(current);
+    }
+
+    fn drop_watches(&mut self, client: ClientId) {
+        self.watchers.retain(|_watched, clients| {
+            clients.retain(|id| *id != client);
+            !clients.is_empty()
+        });
+    }
+
+    // Watches `key` in the selected database for the current client.
+    // Returns false if it already was.
+    pub fn watch(&mut self, key: &str) -> bool {
+        let key = self.normalize_key(key).into_owned();
+        let clients = self.watchers.entry((self.selected, key)).or_default();
+        if clients.contains(&self.client) {
+            return false;
+        }
+        clients.push(self.client);
+        true
+    }
+
+    // A client whose channel is closed, or who never subscribed, loses its
+    // watches. The rest of its session waits for `unsubscribe`, since this
+    // runs in the middle of a change.
+    fn notify(&mut self, key: &str, event: &str) {
+        self.notify_in(self.selected, key, event);
+    }
+
+    // For a change to a database other than the selected one.
+    fn notify_in(&mut self, index: usize, key: &str, event: &str) {
+        let Some(clients) = self.watchers.get(&(index, key.to_string())) else {
+            return;
+        };
+
+        let line = format!("WATCH {} {}", key, event);
+        let mut gone = Vec::new();
+        for client in clients {
+            let sender = self.sessions.get(client).and_then(|s| s.sender.as_ref());
+            match sender {
+                Some(sender) if sender.send(line.clone()).is_ok() => {}
+                _ => gone.push(*client),
+            }
+        }
+        for client in gone {
+            self.drop_watches(client);
+        }
+    }
+
+    // == Write-ahead log ==
+    // One command line per successful mutation, appended once it is applied.
+
+    pub fn wal_enabled(&self) -> bool {
+        self.wal.is_some()
+    }
+
+    // A SELECT goes in first when the line is for a different database than
+    // the one before it.
+    pub fn append_wal(&mut self, line: &str) -> io::Result<()> {
+        let Some(path) = &self.wal else {
+            return Ok(());
+        };
+        let mut lines = Vec::new();
+        if self.wal_db != self.selected {
+            lines.push(format!("SELECT {}", self.selected));
+            self.wal_db = self.selected;
+        }
+        lines.push(line.to_string());
+
+        if let Some(tx) = self
+            .sessions
+            .values_mut()
+            .find_map(|session| session.transaction.as_mut())
+        {
+            tx.wal.extend(lines);
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        for line in lines {
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    // Runs every command in the log at `path`, then keeps appending to it.
+    // A missing log is treated as empty. Returns how many commands ran.
+    // Commands that fail are passed over, since they failed when first run
+    // too, but a line that doesn't tokenize means the log is damaged and
+    // stops the replay.
+    pub fn replay_wal(&mut self, path: &str) -> io::Result<usize> {
+        let mut replayed = 0;
+
+        match File::open(path) {
+            Ok(file) => {
+                for (number, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    let Ok(tokens) = commands::tokenize(&line) else {
+                        return Err(invalid_data(&format!(
+                            "line {} does not parse: {}",
+                            number + 1,
+                            line
+                        )));
+                    };
+                    let input = tokens.iter().map(String::as_str).collect::<Vec<&str>>();
+                    if !input.is_empty() {
+                        commands::handle_command(self, &input);
+                        replayed += 1;
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        self.wal = Some(path.to_string());
+        self.wal_db = self.selected;
+        Ok(replayed)
+    }
+
+    // Rewrites the log as one SET (plus PEXPIREAT) per live key, with a SELECT
+    // before each database past the first.
+    pub fn compact_wal(&mut self) -> io::Result<()> {
+        let Some(path) = &self.wal else {
+            return Ok(());
+        };
+        if let Some(tx) = self
+            .sessions
+            .values_mut()
+            .find_map(|session| session.transaction.as_mut())
+        {
+            tx.compact = true;
+            return Ok(());
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        let wal_db = self.write_databases(&mut file)?;
+        file.flush()?;
+        self.wal_db = wal_db;
+        Ok(())
+    }
+
+    // Every database as the commands that rebuild it, the same ones
+    // compaction writes, for DUMPALL.
+    pub fn dump_script(&self) -> String {
+        let mut script = Vec::new();
+        // Writing to a Vec can't fail.
+        let _ = self.write_databases(&mut script);
+        String::from_utf8_lossy(&script).into_owned()
+    }
+
+    // Runs from database 0, with a SELECT before each later database that
+    // has keys. Returns the database the last line applies to.
+    fn write_databases(&self, file: &mut impl Write) -> io::Result<usize> {
+        let mut current = 0;
+        for (index, db) in self.databases() {
+            if db.iter().next().is_none() {
+                continue;
+            }
+            if index != current {
+                writeln!(file, "SELECT {}", index)?;
+                current = index;
+            }
+            db.write_entries(file)?;
+        }
+        Ok(current)
+    }
+
+    fn write_entries(&self, file: &mut impl Write) -> io::Result<()> {
+        for key in self.keys() {
+            let value = &self.db[key];
+            let key_token = quote(key);
+            match value.typetag {
+                // A line break can't be quoted onto one line, so a value
+                // holding one is written whole as a blob.
+                _ if breaks_lines(value) => {
+                    writeln!(file, "RESTORE {} {}", key_token, value.dump())?
+                }
+                DataType::Null => writeln!(file, "SET {} null", key_token)?,
+                DataType::Hash => {
+                    for (field, text) in value.hash_fields().unwrap_or_default() {
+                        writeln!(
+                            file,
+                            "HSET {} {} {}",
+                            key_token,
+                            quote(&field),
+                            quote(&text)
+                        )?;
+                    }
+                }
+                DataType::Array => {
+                    for item in value
+                        .array_items()
+                        .map(|(_, items)| items)
+                        .unwrap_or_default()
+                    {
+                        // A null item's text is empty, which `RPUSH k null ""`
+                        // reads back. Its display form, "(nil)", wouldn't parse.
+                        let text = match item.typetag {
+                            DataType::Null => String::new(),
+                            _ => item.to_string(),
+                        };
+                        writeln!(
+                            file,
+                            "RPUSH {} {} {}",
+                            key_token,
+                            item.type_name(),
+                            quote(&text)
+                        )?;
+                    }
+                }
+                _ => writeln!(
+                    file,
+                    "SET {} {} {}",
+                    key_token,
+                    value.type_name(),
+                    quote(&value.to_string())
+                )?,
+            }
+            if let Some(at) = value.expires_at {
+                writeln!(file, "{}", expire_line(key, at, value.ttl))?;
+            }
+        }
+        Ok(())
+    }
+
+    // == JSON export ==
+    // {"key": {"type": "int", "value": 42}, ...}, sorted by key.
+    // Non-finite floats have no JSON form, so they are written as strings.
+
+    pub fn export_json(&self) -> String {
+        let mut entries = Vec::new();
+
+        for key in self.keys() {
+            let value = &self.db[key];
+            // Arrays also name their item type.
+            let item_type = match value.array_items() {
+                Some((item_type, _)) => format!("\"of\": \"{}\", ", item_type.name()),
+                None => String::new(),
+            };
+
+            entries.push(format!(
+                "  \"{}\": {{\"type\": \"{}\", {}\"value\": {}}}",
+                json::escape(key),
+                value.type_name(),
+                item_type,
+                json_value(value)
+            ));
+        }
+
+        if entries.is_empty() {
+            return "{}".to_string();
+        }
+        format!("{{\n{}\n}}", entries.join(",\n"))
+    }
+
+    // == JSON import ==
+    // Entries are merged into the current state. Entries with an unknown type
+    // or a bad value are skipped; the returned list explains each one.
+
+    pub fn import_json(&mut self, text: &str) -> Result<(usize, Vec<String>), String> {
+        let JsonValue::Object(entries) = json::parse(text)? else {
+            return Err("top-level value must be an object".to_string());
+        };
+
+        let mut imported = 0;
+        let mut skipped = Vec::new();
+
+        for (key, entry) in entries {
+            if !is_valid_key(&key) {
+                skipped.push(format!("{:?}: invalid key", key));
+                continue;
+            }
+
+            let value_type = match entry.get("type") {
+                Some(JsonValue::String(t))
+                    if TYPE_NAMES.contains(&t.as_str()) || t == "array" || t == "hash" =>
+                {
+                    t
+                }
+                Some(JsonValue::String(t)) => {
+                    skipped.push(format!("{}: unknown type '{}'", key, t));
+                    continue;
+                }
+                _ => {
+                    skipped.push(format!("{}: missing type", key));
+                    continue;
+                }
+            };
+
+            let value = match value_type.as_str() {
+                "array" => array_from_json(&entry),
+                "hash" => hash_from_json(&entry),
+                _ => value_from_json(value_type, entry.get("value")),
+            };
+
+            match value.and_then(|value| self.check_size(&value).map(|_| value)) {
+                Ok(value) => {
+                    self.set(key, value);
+                    imported += 1;
+                }
+                Err(e) => skipped.push(format!("{}: {}", key, e)),
+            }
+        }
+
+        Ok((imported, skipped))
+    }
+
+    // == CSV export ==
+    // A `key,type,value` header, then one row per key in key order. Arrays
+    // and hashes have no CSV form, so they are left out along with values
+    // that can't be decoded; the returned list explains each one.
+
+    pub fn export_csv(&self) -> (String, Vec<String>) {
+        let mut text = String::from("key,type,value\n");
+        let mut skipped = Vec::new();
+
+        for key in self.keys() {
+            let value = &self.db[key];
+            let shown = match value.typetag {
+                DataType::Array | DataType::Hash => {
+                    skipped.push(format!("{}: {} values need EXPORT", key, value.type_name()));
+                    continue;
+                }
+                DataType::Null => Ok(String::new()),
+                _ => value.to_display_string(),
+            };
+            let shown = match shown {
+                Ok(shown) => shown,
+                Err(e) => {
+                    skipped.push(format!("{}: {}", key, e));
+                    continue;
+                }
+            };
+
+            text.push_str(&format!(
+                "{},{},{}\n",
+                csv::field(key),
+                value.type_name(),
+                csv::field(&shown)
+            ));
+        }
+
+        (text, skipped)
+    }
+
+    // == CSV import ==
+    // Rows are `key,type,value`, merged like IMPORT. A first row that reads
+    // `key,type,value` is a header and skipped. Bad rows are skipped too,
+    // with a warning naming the row.
+
+    pub fn import_csv(&mut self, text: &str) -> Result<(usize, Vec<String>), String> {
+        let rows = csv::parse(text)?;
+        let mut imported = 0;
+        let mut skipped = Vec::new();
+
+        for (i, row) in rows.iter().enumerate() {
+            let number = i + 1;
+            if i == 0
+                && row.len() == 3
+                && row[0].eq_ignore_ascii_case("key")
+                && row[1].eq_ignore_ascii_case("type")
+                && row[2].eq_ignore_ascii_case("value")
+            {
+                continue;
+            }
+
+            let [key, value_type, text] = row.as_slice() else {
+                skipped.push(format!(
+                    "row {}: expected 3 fields, found {}",
+                    number,
+                    row.len()
+                ));
+                continue;
+            };
+            if !is_valid_key(key) {
+                skipped.push(format!("row {}: invalid key", number));
+                continue;
+            }
+            match DbValue::parse(value_type, text)
+                .and_then(|value| self.check_size(&value).map(|_| value))
+            {
+                Ok(value) => {
+                    self.set(key.clone(), value);
+                    imported += 1;
+                }
+                Err(e) => skipped.push(format!("row {} ({}): {}", number, key, e)),
+            }
+        }
+
+        Ok((imported, skipped))
+    }
+
+    // Loading builds a fresh Database; the REPL replaces its current state with it.
+    pub fn load(path: &str) -> io::Result<Database> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("missing or bad header"));
+        }
+
+        let mut pos = MAGIC.len();
+        let version = match bytes.get(pos) {
+            None | Some(0) => 0,
+            Some(&version) => {
+                pos += 1;
+                version
+            }
+        };
+        if version > FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported database version {}", version),
+            ));
+        }
+
+        let mut db = Database::new();
+
+        while pos < bytes.len() {
+            let key_len = read_u32(&bytes, &mut pos)? as usize;
+            if key_len == 0 {
+                let index = read_slice(&bytes, &mut pos, 1)?[0] as usize;
+                check_database(index).map_err(|e| invalid_data(&e))?;
+                db.switch_to(index);
+                continue;
+            }
+            let key = read_slice(&bytes, &mut pos, key_len)?;
+            let key =
+                String::from_utf8(key.to_vec()).map_err(|_| invalid_data("key is not utf-8"))?;
+
+            let tag = read_slice(&bytes, &mut pos, 1)?[0];
+            let typetag = DataType::from_tag(tag)
+                .ok_or_else(|| invalid_data(&format!("unknown typetag {}", tag)))?;
+
+            let data_len = read_u32(&bytes, &mut pos)? as usize;
+            let data = read_slice(&bytes, &mut pos, data_len)?.to_vec();
+
+            let expires_at = match read_u64(&bytes, &mut pos)? {
+                0 => None,
+                at => Some(
+                    clock::from_millis(at).ok_or_else(|| invalid_data("expiry out of range"))?,
+                ),
+            };
+            let ttl = match read_u64(&bytes, &mut pos)? {
+                0 => None,
+                window => Some(Duration::from_millis(window)),
+            };
+
+            db.set(
+                key,
+                DbValue {
+                    typetag,
+                    data,
+                    expires_at,
+                    ttl,
+                },
+            );
+        }
+
+        db.switch_to(0);
+        Ok(migrate(db, version))
+    }
+}
+
+// Whether any text in `value` holds a line break, which a quoted token
+// can't carry.
+fn breaks_lines(value: &DbValue) -> bool {
+    let texts = match value.typetag {
+        DataType::Hash => value
+            .hash_fields()
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(field, text)| [field, text])
+            .collect(),
+        DataType::Array => value
+            .array_items()
+            .map(|(_, items)| items)
+            .unwrap_or_default()
+            .iter()
+            .map(DbValue::to_string)
+            .collect(),
+        _ => vec![value.to_string()],
+    };
+    texts.iter().any(|text| text.contains(['\n', '\r']))
+}
+
+// Brings a database read from an older format version up to date.
+// Versions 0 and 1 share the entry layout, and files before version 3 only
+// have database 0.
+fn migrate(mut db: Database, from_version: u8) -> Database {
+    if from_version < 2 {
+        for value in db.db.values_mut() {
+            if let Some((DataType::Bool, mut items)) = value.array_items() {
+                items.iter_mut().for_each(migrate_bool);
+                value.data = DbValue::from_array(&items).data;
+            } else {
+                migrate_bool(value);
+            }
+        }
+    }
+    db
+}
+
+// Text bools from before version 2 become a single byte.
+fn migrate_bool(value: &mut DbValue) {
+    if value.typetag != DataType::Bool {
+        return;
+    }
+    match value.data.as_slice() {
+        b"true" => value.data = vec![1],
+        b"false" => value.data = vec![0],
+        _ => {}
+    }
+}
+
+// The JSON form of a value as written by EXPORT.
+fn json_value(value: &DbValue) -> String {
+    match value.typetag {
+        DataType::String => {
+            format!(
+                "\"{}\"",
+                json::escape(&String::from_utf8_lossy(&value.data))
+            )
+        }
+        // Corrupt values are written as a string, which IMPORT reports and
+        // skips.
+        DataType::Int | DataType::Bool | DataType::UInt => match value.to_display_string() {
+            Ok(s) => s,
+            Err(e) => format!("\"<{}>\"", e),
+        },
+        DataType::Float => match value.as_float() {
+            Some(f) if f.is_finite() => format!("{:?}", f),
+            Some(f) => format!("\"{}\"", f),
+            None => format!("\"{}\"", value),
+        },
+        DataType::Bytes => format!("\"{}\"", to_hex(&value.data)),
+        DataType::Null => "null".to_string(),
+        DataType::DateTime => format!("\"{}\"", value),
+        DataType::Array => match value.array_items() {
+            Some((_, items)) => format!(
+                "[{}]",
+                items
+                    .iter()
+                    .map(json_value)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            None => "\"<invalid array data>\"".to_string(),
+        },
+        DataType::Hash => match value.hash_fields() {
+            Some(fields) => format!(
+                "{{{}}}",
+                fields
+                    .iter()
+                    .map(|(field, text)| format!(
+                        "\"{}\": \"{}\"",
+                        json::escape(field),
+                        json::escape(text)
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            None => "\"<invalid hash data>\"".to_string(),
+        },
+    }
+}
+
+// Reads back a single non-array value of `value_type` from IMPORT.
+fn value_from_json(value_type: &str, json: Option<&JsonValue>) -> Result<DbValue, String> {
+    let value_str = match json {
+        Some(JsonValue::String(s)) | Some(JsonValue::Number(s)) => s.clone(),
+        Some(JsonValue::Bool(b)) => b.to_string(),
+        Some(JsonValue::Null) => String::new(),
+        _ => return Err("missing or unsupported value".to_string()),
+    };
+    DbValue::parse(value_type, &value_str)
+}
+
+fn array_from_json(entry: &JsonValue) -> Result<DbValue, String> {
+    let item_type = match entry.get("of") {
+        Some(JsonValue::String(t)) if TYPE_NAMES.contains(&t.as_str()) => t,
+        _ => return Err("missing or unknown array item type".to_string()),
+    };
+    let Some(JsonValue::Array(items)) = entry.get("value") else {
+        return Err("array value must be a list".to_string());
+    };
+    if items.is_empty() {
+        return Err("array is empty".to_string());
+    }
+
+    let items = items
+        .iter()
+        .map(|item| value_from_json(item_type, Some(item)))
+        .collect::<Result<Vec<DbValue>, String>>()?;
+    Ok(DbValue::from_array(&items))
+}
+
+fn hash_from_json(entry: &JsonValue) -> Result<DbValue, String> {
+    let Some(JsonValue::Object(fields)) = entry.get("value") else {
+        return Err("hash value must be an object".to_string());
+    };
+
+    let fields = fields
+        .iter()
+        .map(|(field, text)| match text {
+            JsonValue::String(text) => Ok((field.clone(), text.clone())),
+            _ => Err(format!("hash field '{}' must be a string", field)),
+        })
+        .collect::<Result<Vec<(String, String)>, String>>()?;
+    Ok(DbValue::from_hash(&fields))
+}
+
+// Keys must be non-blank and free of control characters, which would
+// break the line-based protocol and the WAL.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.trim().is_empty() && !key.chars().any(char::is_control)
+}
+
+fn check_database(index: usize) -> Result<(), String> {
+    if index >= DATABASES {
+        return Err(format!("Database index must be 0 to {}", DATABASES - 1));
+    }
+    Ok(())
+}
+
+// Enforces --max-value-bytes on a value's encoded length.
+fn value_fits(max: Option<usize>, len: usize) -> Result<(), String> {
+    match max {
+        Some(max) if len > max => Err("value too large".to_string()),
+        _ => Ok(()),
+    }
+}
+
+// Whether DbValue::parse takes this as a type, aliases included.
+pub fn is_type_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    TYPE_NAMES.contains(&name.as_str()) || matches!(name.as_str(), "string" | "i64" | "f64" | "u64")
+}
+
+// The type SET picks when none is given. First match wins:
+// - `true` / `false` (exact, lowercase) are bools
+// - anything that fits an i64 is an int
+// - anything else with a digit that parses as a finite f64 is a float
+// - the rest are strings
+// This is lossy for strings that happen to look like numbers: `007` becomes
+// the int 7 and `1e3` the float 1000. Give the type explicitly to keep them.
+// Integers too large for an i64 stay strings rather than becoming imprecise
+// floats.
+pub fn infer_type(value: &str) -> DataType {
+    if value == "true" || value == "false" {
+        DataType::Bool
+    } else if value.parse::<i64>().is_ok() {
+        DataType::Int
+    } else if value.contains(['.', 'e', 'E'])
+        && value.bytes().any(|b| b.is_ascii_digit())
+        && value.parse::<f64>().is_ok_and(f64::is_finite)
+    {
+        DataType::Float
+    } else {
+        DataType::String
+    }
+}
+
+// Number parsing that tells values too large for the type apart from
+// values that aren't numbers at all.
+
+pub fn parse_int(text: &str) -> Result<i64, String> {
+    text.parse::<i64>().map_err(|e| match e.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+            "integer out of range for i64".to_string()
+        }
+        _ => "not a valid integer".to_string(),
+    })
+}
+
+pub fn parse_uint(text: &str) -> Result<u64, String> {
+    text.parse::<u64>().map_err(|e| match e.kind() {
+        IntErrorKind::PosOverflow => "integer out of range for u64".to_string(),
+        _ => "not a valid unsigned integer".to_string(),
+    })
+}
+
+// Literals that overflow to infinity are rejected; `inf` itself is allowed.
+pub fn parse_float(text: &str) -> Result<f64, String> {
+    let f = text
+        .parse::<f64>()
+        .map_err(|_| "not a valid float".to_string())?;
+    let literal = text.trim_start_matches(['+', '-']).to_ascii_lowercase();
+    if f.is_infinite() && literal != "inf" && literal != "infinity" {
+        return Err("float out of range for f64".to_string());
+    }
+    Ok(f)
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) {
+        return Err("Hex value must have an even number of digits".to_string());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or("invalid"), 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| "Invalid hex value".to_string())
+}
+
+// 1536 -> "1.5 KB".
+pub fn human_size(bytes: usize) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// == Helpers for reading the disk format. ==
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_slice<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> io::Result<&'a [u8]> {
+    let end = pos
+        .checked_add(len)
+        .filter(|end| *end <= bytes.len())
+        .ok_or_else(|| invalid_data("file is truncated"))?;
+    let slice = &bytes[*pos..end];
+    *pos = end;
+    Ok(slice)
+}
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> io::Result<u32> {
+    let slice = read_slice(bytes, pos, 4)?;
+    Ok(u32::from_be_bytes(slice.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let slice = read_slice(bytes, pos, 8)?;
+    Ok(u64::from_be_bytes(slice.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_path;
+
+    fn run(db: &mut Database, line: &str) -> CommandResponse {
+        let tokens = tokenize(line).unwrap();
+        let input = tokens.iter().map(String::as_str).collect::<Vec<&str>>();
+        handle_command(db, &input)
+    }
+
+    // A database whose clock only moves when the test advances it.
+    fn with_manual_clock() -> (Database, Clock) {
+        let clock = Clock::manual(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let mut db = Database::new();
+        db.set_clock(clock.clone());
+        (db, clock)
+    }
+
+    fn ttl(db: &mut Database, key: &str) -> CommandResponse {
+        run(db, &format!("TTL {}", key))
+    }
+
+    fn value(text: &str) -> CommandResponse {
+        CommandResponse::Value(text.to_string())
+    }
+
+    #[test]
+    fn ints_round_trip() {
+        for i in [0, 1, -1, 12345, -12345, i64::MAX, i64::MIN] {
+            let mut db = Database::new();
+            db.set("k".into(), DbValue::from_i64(i));
+            assert_eq!(db.get("k").and_then(DbValue::as_int), Some(i));
+            assert_eq!(db.get("k").unwrap().to_string(), i.to_string());
+        }
+    }
+
+    #[test]
+    fn floats_round_trip() {
+        for f in [0.0, -0.0, 1.5, -2.25, f64::MAX, f64::MIN, 1e300, -1e-300] {
+            let back = DbValue::from_f64(f).as_float().unwrap();
+            // Bits, so -0.0 doesn't pass as 0.0.
+            assert_eq!(back.to_bits(), f.to_bits(), "{}", f);
+        }
+        assert!(DbValue::from_f64(f64::NAN).as_float().unwrap().is_nan());
+        assert_eq!(
+            DbValue::from_f64(f64::INFINITY).as_float(),
+            Some(f64::INFINITY)
+        );
+        assert_eq!(DbValue::from_f64(-0.0).to_string(), "-0");
+    }
+
+    #[test]
+    fn int_and_float_accessors_check_the_type() {
+        assert_eq!(DbValue::from_i64(1).as_float(), None);
+        assert_eq!(DbValue::from_f64(1.0).as_int(), None);
+    }
+
+    #[test]
+    fn save_writes_the_documented_layout() {
+        let path = temp_path("save-layout");
+        let mut db = Database::new();
+        db.set("k".into(), DbValue::from_str("hi"));
+        db.select(2).unwrap();
+        db.set("n".into(), DbValue::from_i64(1));
+        db.save(&path).unwrap();
+
+        let mut expected = b"PILL".to_vec();
+        expected.push(FORMAT_VERSION);
+        // k = "hi", no expiry.
+        expected.extend([0, 0, 0, 1, b'k', 0, 0, 0, 0, 2, b'h', b'i']);
+        expected.extend([0; 16]);
+        // Switch to database 2.
+        expected.extend([0, 0, 0, 0, 2]);
+        // n = 1, no expiry.
+        expected.extend([0, 0, 0, 1, b'n', 1, 0, 0, 0, 8]);
+        expected.extend(1i64.to_be_bytes());
+        expected.extend([0; 16]);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn save_reports_an_unwritable_path() {
+        let mut db = Database::new();
+        let path = temp_path("no-such-dir/db.pill");
+        let CommandResponse::Error(e) = run(&mut db, &format!("SAVE {}", path)) else {
+            panic!("SAVE into a missing directory worked");
+        };
+        assert!(e.starts_with("Failed to save: "), "{}", e);
+    }
+
+    #[test]
+    fn load_reads_back_what_save_wrote() {
+        let path = temp_path("load-valid");
+        let mut db = Database::new();
+        db.set("s".into(), DbValue::from_str("text"));
+        db.set("i".into(), DbValue::from_i64(-7));
+        db.set("f".into(), DbValue::from_f64(2.5));
+        db.set("b".into(), DbValue::from_bool(true));
+        db.save(&path).unwrap();
+
+        let mut loaded = Database::load(&path).unwrap();
+        assert_eq!(loaded.keys(), vec!["b", "f", "i", "s"]);
+        assert_eq!(loaded.get("s").and_then(DbValue::as_string), Some("text"));
+        assert_eq!(loaded.get("i").and_then(DbValue::as_int), Some(-7));
+        assert_eq!(loaded.get("f").and_then(DbValue::as_float), Some(2.5));
+        assert_eq!(loaded.get("b").and_then(DbValue::as_bool), Some(true));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn load_rejects_empty_bad_and_truncated_files() {
+        let path = temp_path("load-bad");
+        let mut valid = Database::new();
+        valid.set("k".into(), DbValue::from_str("value"));
+        valid.save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        for contents in [&b""[..], b"NOPE\x04", &bytes[..bytes.len() - 1]] {
+            std::fs::write(&path, contents).unwrap();
+            let Err(e) = Database::load(&path) else {
+                panic!("loaded {:?}", contents);
+            };
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{:?}", contents);
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn export_json_writes_every_type() {
+        let mut db = Database::new();
+        db.set("a".into(), DbValue::from_str("plain"));
+        db.set("b".into(), DbValue::from_i64(-3));
+        db.set("c".into(), DbValue::from_f64(2.5));
+        db.set("d".into(), DbValue::from_bool(false));
+        db.set("e".into(), DbValue::from_u64(u64::MAX));
+        db.set("f".into(), DbValue::from_bytes(&[0, 255]));
+        db.set("g".into(), DbValue::null());
+        db.set("h".into(), DbValue::from_datetime(0));
+        db.set("i".into(), DbValue::from_array(&[DbValue::from_i64(1)]));
+        db.set(
+            "j".into(),
+            DbValue::from_hash(&[("field".into(), "text".into())]),
+        );
+        db.set("k".into(), DbValue::from_f64(f64::NAN));
+
+        let expected = [
+            r#"  "a": {"type": "str", "value": "plain"}"#,
+            r#"  "b": {"type": "int", "value": -3}"#,
+            r#"  "c": {"type": "float", "value": 2.5}"#,
+            r#"  "d": {"type": "bool", "value": false}"#,
+            r#"  "e": {"type": "uint", "value": 18446744073709551615}"#,
+            r#"  "f": {"type": "bytes", "value": "00ff"}"#,
+            r#"  "g": {"type": "null", "value": null}"#,
+            r#"  "h": {"type": "datetime", "value": "1970-01-01T00:00:00Z"}"#,
+            r#"  "i": {"type": "array", "of": "int", "value": [1]}"#,
+            r#"  "j": {"type": "hash", "value": {"field": "text"}}"#,
+            r#"  "k": {"type": "float", "value": "NaN"}"#,
+        ];
+        assert_eq!(
+            db.export_json(),
+            format!("{{\n{}\n}}", expected.join(",\n"))
+        );
+    }
+
+    #[test]
+    fn export_json_escapes_special_characters() {
+        let mut db = Database::new();
+        db.set(
+            "say \"hi\"".into(),
+            DbValue::from_str("tab\there\nback\\slash \u{1} é"),
+        );
+        assert_eq!(
+            db.export_json(),
+            "{\n  \"say \\\"hi\\\"\": {\"type\": \"str\", \
+             \"value\": \"tab\\there\\nback\\\\slash \\u0001 é\"}\n}"
+        );
+        assert_eq!(Database::new().export_json(), "{}");
+    }
+
+    #[test]
+    fn import_json_merges_well_formed_entries() {
+        let mut db = Database::new();
+        db.set("kept".into(), DbValue::from_i64(1));
+        let text = r#"{
+            "s": {"type": "str", "value": "line\nbreak"},
+            "n": {"type": "int", "value": 42},
+            "f": {"type": "float", "value": -1.5},
+            "b": {"type": "bool", "value": true},
+            "list": {"type": "array", "of": "int", "value": [1, 2]}
+        }"#;
+        assert_eq!(db.import_json(text), Ok((5, Vec::new())));
+        assert_eq!(db.count(), 6);
+        assert_eq!(
+            db.get("s").and_then(DbValue::as_string),
+            Some("line\nbreak")
+        );
+        assert_eq!(db.get("n").and_then(DbValue::as_int), Some(42));
+        assert_eq!(db.get("f").and_then(DbValue::as_float), Some(-1.5));
+        assert_eq!(db.get("b").and_then(DbValue::as_bool), Some(true));
+        assert_eq!(db.get("list").unwrap().to_string(), "[1, 2]");
+    }
+
+    #[test]
+    fn import_json_skips_unknown_types() {
+        let mut db = Database::new();
+        let text = r#"{"a": {"type": "blob", "value": "x"}, "b": {"type": "int", "value": 1}}"#;
+        assert_eq!(
+            db.import_json(text),
+            Ok((1, vec!["a: unknown type 'blob'".to_string()]))
+        );
+        assert_eq!(db.keys(), vec!["b"]);
+    }
+
+    #[test]
+    fn import_json_rejects_malformed_json() {
+        let mut db = Database::new();
+        for text in ["", "{", r#"{"a": }"#, "[1, 2]", r#"{"a": 1} extra"#] {
+            assert!(db.import_json(text).is_err(), "{}", text);
+        }
+        assert_eq!(db.count(), 0);
+    }
+
+    #[test]
+    fn invalid_utf8_strings_do_not_panic() {
+        let value = DbValue {
+            typetag: DataType::String,
+            data: vec![b'a', 0xff, 0xfe],
+            expires_at: None,
+            ttl: None,
+        };
+        assert_eq!(value.as_string(), None);
+        assert_eq!(value.to_display_string(), Err("invalid utf-8".to_string()));
+        assert_eq!(value.to_string(), "<invalid utf-8>");
+
+        let mut db = Database::new();
+        db.set("k".into(), value);
+        assert_eq!(
+            run(&mut db, "GET k"),
+            CommandResponse::Error("Corrupt value at k: invalid utf-8".into())
+        );
+    }
+
+    #[test]
+    fn keys_are_gone_once_their_time_passes() {
+        let (mut db, clock) = with_manual_clock();
+        run(&mut db, "SET k 1");
+        run(&mut db, "EXPIRE k 10");
+
+        clock.advance(Duration::from_millis(9_999));
+        assert!(db.exists("k"));
+        clock.advance(Duration::from_millis(1));
+        assert!(!db.exists("k"));
+        assert_eq!(
+            run(&mut db, "GET k"),
+            CommandResponse::Error("Key not found".into())
+        );
+        assert_eq!(db.count(), 0);
+    }
+
+    #[test]
+    fn ttl_counts_down_and_touch_and_persist_change_it() {
+        let (mut db, clock) = with_manual_clock();
+        assert_eq!(ttl(&mut db, "k"), value("-2"));
+        run(&mut db, "SET k 1");
+        assert_eq!(ttl(&mut db, "k"), value("-1"));
+
+        run(&mut db, "EXPIRE k 10");
+        assert_eq!(ttl(&mut db, "k"), value("10"));
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(ttl(&mut db, "k"), value("4"));
+
+        assert_eq!(run(&mut db, "TOUCH k"), value("1"));
+        assert_eq!(ttl(&mut db, "k"), value("10"));
+
+        assert_eq!(run(&mut db, "PERSIST k"), value("1"));
+        assert_eq!(ttl(&mut db, "k"), value("-1"));
+        clock.advance(Duration::from_secs(60));
+        assert!(db.exists("k"));
+    }
+
+    #[test]
+    fn save_and_load_keep_expiries() {
+        let path = temp_path("save-expiry");
+        let (mut db, clock) = with_manual_clock();
+        run(&mut db, "SET k 1");
+        run(&mut db, "SET kept 2");
+        run(&mut db, "EXPIRE k 10");
+        db.save(&path).unwrap();
+
+        clock.advance(Duration::from_secs(4));
+        let mut loaded = Database::new();
+        loaded.set_clock(clock.clone());
+        loaded.replace(Database::load(&path).unwrap());
+        assert_eq!(ttl(&mut loaded, "k"), value("6"));
+        assert_eq!(ttl(&mut loaded, "kept"), value("-1"));
+        // The window came along too, so TOUCH restarts it.
+        run(&mut loaded, "TOUCH k");
+        assert_eq!(ttl(&mut loaded, "k"), value("10"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn loading_drops_keys_that_expired_on_disk() {
+        let path = temp_path("load-expired");
+        let (mut db, clock) = with_manual_clock();
+        run(&mut db, "SET k 1");
+        run(&mut db, "EXPIRE k 10");
+        db.save(&path).unwrap();
+
+        clock.advance(Duration::from_secs(10));
+        let mut loaded = Database::new();
+        loaded.set_clock(clock);
+        loaded.replace(Database::load(&path).unwrap());
+        assert_eq!(loaded.count(), 0);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn uints_round_trip() {
+        for u in [0, 1, u64::MAX] {
+            assert_eq!(DbValue::from_u64(u).as_u64(), Some(u));
+            let parsed = DbValue::parse("uint", &u.to_string()).unwrap();
+            assert_eq!(parsed.as_u64(), Some(u));
+        }
+        assert_eq!(
+            DbValue::from_u64(u64::MAX).to_string(),
+            "18446744073709551615"
+        );
+        assert!(DbValue::parse("u64", "18446744073709551616").is_err());
+        assert!(DbValue::parse("uint", "-1").is_err());
+        assert_eq!(DbValue::from_u64(1).as_int(), None);
+    }
+
+    #[test]
+    fn bytes_round_trip_through_hex() {
+        assert_eq!(DbValue::from_bytes(&[]).as_bytes(), Some(&[][..]));
+        assert_eq!(DbValue::from_hex("").unwrap().as_bytes(), Some(&[][..]));
+
+        let value = DbValue::from_hex("00Ff7a").unwrap();
+        assert_eq!(value.as_bytes(), Some(&[0x00, 0xff, 0x7a][..]));
+        assert_eq!(value.to_string(), "00ff7a");
+        assert_eq!(DbValue::from_str("00").as_bytes(), None);
+    }
+
+    #[test]
+    fn bytes_reject_bad_hex() {
+        assert_eq!(
+            DbValue::from_hex("abc").unwrap_err(),
+            "Hex value must have an even number of digits"
+        );
+        for hex in ["zz", "0g", "é0"] {
+            assert!(DbValue::from_hex(hex).is_err(), "{}", hex);
+        }
+    }
+
+    #[test]
+    fn display_shows_each_type_decoded() {
+        let fields = [
+            ("a".to_string(), "1".to_string()),
+            ("b".into(), "x y".into()),
+        ];
+        for (value, shown) in [
+            (DbValue::from_str("two words"), "two words"),
+            (DbValue::from_i64(-42), "-42"),
+            (DbValue::from_f64(0.1), "0.1"),
+            (DbValue::from_f64(3.0), "3"),
+            (DbValue::from_bool(true), "true"),
+            (DbValue::from_u64(7), "7"),
+            (DbValue::from_bytes(&[0xde, 0xad]), "dead"),
+            (DbValue::null(), "(nil)"),
+            (DbValue::from_datetime(951_782_400), "2000-02-29T00:00:00Z"),
+            (
+                DbValue::from_array(&[DbValue::from_str("a"), DbValue::from_str("b")]),
+                "[a, b]",
+            ),
+            (DbValue::from_hash(&fields), "{a: 1, b: x y}"),
+        ] {
+            assert_eq!(value.to_string(), shown, "{:?}", value.typetag);
+        }
+    }
+
+    #[test]
+    fn wal_replay_recovers_the_data() {
+        let path = temp_path("wal-recover");
+        let _ = std::fs::remove_file(&path);
+        {
+            let mut db = Database::new();
+            assert_eq!(db.replay_wal(&path).unwrap(), 0);
+            for line in [
+                "SET a 1",
+                r#"SET b str "two words""#,
+                "INCR a",
+                "SET gone 1",
+                "DELETE gone",
+                "GET a",
+                "RPUSH list x",
+            ] {
+                run(&mut db, line);
+            }
+        }
+
+        let mut db = Database::new();
+        // GET is not logged.
+        assert_eq!(db.replay_wal(&path).unwrap(), 6);
+        assert_eq!(db.keys(), vec!["a", "b", "list"]);
+        assert_eq!(db.get("a").and_then(DbValue::as_int), Some(2));
+        assert_eq!(db.get("b").and_then(DbValue::as_string), Some("two words"));
+        // Replaying resumes logging to the same file.
+        run(&mut db, "SET c 3");
+        assert_eq!(Database::new().replay_wal(&path).unwrap(), 7);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn rejected_commands_leave_no_wal_line() {
+        let path = temp_path("wal-rejected");
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::new();
+        db.replay_wal(&path).unwrap();
+        run(&mut db, "SET s str text");
+        for line in [
+            "SET",
+            "SET k int nope",
+            "INCR s",
+            "RENAME missing other",
+            "DELETE",
+        ] {
+            assert!(
+                matches!(run(&mut db, line), CommandResponse::Error(_)),
+                "{}",
+                line
+            );
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "SET s str text\n");
+        let mut replayed = Database::new();
+        assert_eq!(replayed.replay_wal(&path).unwrap(), 1);
+        assert_eq!(replayed.keys(), vec!["s"]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn wal_replay_keeps_the_original_deadline() {
+        let path = temp_path("wal-expiry");
+        let _ = std::fs::remove_file(&path);
+        let (mut db, clock) = with_manual_clock();
+        db.replay_wal(&path).unwrap();
+        run(&mut db, "SET short 1");
+        run(&mut db, "EXPIRE short 10");
+        run(&mut db, "SET long 1");
+        run(&mut db, "EXPIRE long 100");
+
+        // A restart 30 seconds later must not give `short` a fresh window.
+        clock.advance(Duration::from_secs(30));
+        let mut replayed = Database::new();
+        replayed.set_clock(clock.clone());
+        replayed.replay_wal(&path).unwrap();
+        assert!(!replayed.exists("short"));
+        assert_eq!(ttl(&mut replayed, "long"), value("70"));
+
+        // Compaction writes the deadline, not what was left of it.
+        replayed.compact_wal().unwrap();
+        clock.advance(Duration::from_secs(30));
+        let mut compacted = Database::new();
+        compacted.set_clock(clock);
+        compacted.replay_wal(&path).unwrap();
+        assert_eq!(ttl(&mut compacted, "long"), value("40"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn expiry_follows_the_clock_in_every_database() {
+        let (mut db, clock) = with_manual_clock();
+        run(&mut db, "SELECT 3");
+        run(&mut db, "SET k 1");
+        run(&mut db, "EXPIRE k 5");
+        run(&mut db, "SELECT 0");
+        clock.advance(Duration::from_secs(5));
+        run(&mut db, "SELECT 3");
+        assert!(!db.exists("k"));
+    }
+
+    #[test]
+    fn max_value_bytes_allows_up_to_the_limit() {
+        let mut db = Database::new();
+        db.set_max_value_bytes(Some(5));
+        assert_eq!(
+            run(&mut db, "SET k str 12345"),
+            CommandResponse::Ok(String::new())
+        );
+        assert_eq!(
+            run(&mut db, "SET k str abcde"),
+            CommandResponse::Ok(String::new())
+        );
+        assert_eq!(
+            run(&mut db, "SET k str abcdef"),
+            CommandResponse::Error("value too large".into())
+        );
+        // An int is 8 bytes however small it looks.
+        assert_eq!(
+            run(&mut db, "SET k int 1"),
+            CommandResponse::Error("value too large".into())
+        );
+    }
+
+    #[test]
+    fn imports_skip_values_over_max_value_bytes() {
+        let mut db = Database::new();
+        db.set_max_value_bytes(Some(5));
+
+        let json = r#"{"a": {"type": "str", "value": "short"}, "b": {"type": "str", "value": "much too long"}}"#;
+        let (imported, skipped) = db.import_json(json).unwrap();
+        assert_eq!(imported, 1);
+        assert_eq!(skipped, vec!["b: value too large".to_string()]);
+
+        let (imported, skipped) = db.import_csv("c,str,ok\nd,str,far too long\n").unwrap();
+        assert_eq!(imported, 1);
+        assert_eq!(skipped, vec!["row 2 (d): value too large".to_string()]);
+        assert_eq!(db.keys(), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn load_skips_values_over_max_value_bytes() {
+        let path = temp_path("load-max-value");
+        let mut source = Database::new();
+        source.set("small".into(), DbValue::from_str("tiny"));
+        source.set("big".into(), DbValue::from_str("enormous value"));
+        source.save(&path).unwrap();
+
+        let mut db = Database::new();
+        db.set_max_value_bytes(Some(5));
+        let skipped = db.replace(Database::load(&path).unwrap());
+        assert_eq!(skipped, vec!["big: value too large".to_string()]);
+        assert_eq!(db.keys(), vec!["small"]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn wal_replays_strings_with_newlines() {
+        let path = temp_path("wal-newline");
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::new();
+        db.replay_wal(&path).unwrap();
+        db.import_json(
+            r#"{"k": {"type": "str", "value": "line1\nline2"},
+                "j": {"type": "str", "value": "a\rb"}}"#,
+        )
+        .unwrap();
+        db.compact_wal().unwrap();
+        run(&mut db, "SET after int 1");
+
+        let mut replayed = Database::new();
+        assert_eq!(replayed.replay_wal(&path).unwrap(), 3);
+        assert_eq!(replayed.get("after").and_then(DbValue::as_int), Some(1));
+        assert_eq!(
+            replayed.get("k").and_then(DbValue::as_string),
+            Some("line1\nline2")
+        );
+        assert_eq!(replayed.get("j").and_then(DbValue::as_string), Some("a\rb"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn wal_replay_stops_at_a_damaged_line() {
+        let path = temp_path("wal-damaged");
+        std::fs::write(&path, "SET a 1\nSET b \"open\nSET c 3\n").unwrap();
+
+        let mut db = Database::new();
+        let e = db.replay_wal(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("line 2"), "{}", e);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn rollback_undoes_every_change_since_begin() {
+        let mut db = Database::new();
+        run(&mut db, "SET kept 1");
+        run(&mut db, "BEGIN");
+        run(&mut db, "SET a 1");
+        run(&mut db, "SET kept 2");
+        run(&mut db, "DELETE kept");
+        assert!(!db.is_dirty());
+        assert_eq!(
+            run(&mut db, "ROLLBACK"),
+            CommandResponse::Ok("Transaction rolled back".into())
+        );
+        assert_eq!(db.keys(), vec!["kept"]);
+        assert_eq!(db.get("kept").and_then(DbValue::as_int), Some(1));
+    }
+
+    #[test]
+    fn commit_applies_the_changes_and_writes_the_wal() {
+        let path = temp_path("wal-commit");
+        let _ = std::fs::remove_file(&path);
+        let mut db = Database::new();
+        db.replay_wal(&path).unwrap();
+        run(&mut db, "BEGIN");
+        run(&mut db, "SET a 1");
+        run(&mut db, "SET b 2");
+        assert_eq!(std::fs::read_to_string(&path).unwrap_or_default(), "");
+        assert_eq!(
+            run(&mut db, "COMMIT"),
+            CommandResponse::Ok("Transaction committed".into())
+        );
+        assert_eq!(db.keys(), vec!["a", "b"]);
+        assert!(db.is_dirty());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "SET a 1\nSET b 2\n"
+        );
+        assert!(matches!(run(&mut db, "COMMIT"), CommandResponse::Error(_)));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn another_clients_transaction_holds_back_writes() {
+        let mut db = Database::new();
+        db.set_client(1);
+        run(&mut db, "BEGIN");
+        run(&mut db, "SET a 1");
+
+        db.set_client(2);
+        assert!(matches!(run(&mut db, "SET b 2"), CommandResponse::Error(_)));
+        assert!(matches!(run(&mut db, "BEGIN"), CommandResponse::Error(_)));
+        assert!(matches!(
+            run(&mut db, "ROLLBACK"),
+            CommandResponse::Error(_)
+        ));
+        // Reads still work.
+        assert_eq!(run(&mut db, "COUNT"), CommandResponse::Value("1".into()));
+
+        db.set_client(1);
+        run(&mut db, "COMMIT");
+        db.set_client(2);
+        assert_eq!(run(&mut db, "SET b 2"), CommandResponse::Ok(String::new()));
+        assert_eq!(db.keys(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn disconnecting_rolls_back_an_open_transaction() {
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut db = Database::new();
+        db.subscribe(1, sender);
+        db.set_client(1);
+        run(&mut db, "BEGIN");
+        run(&mut db, "SET a 1");
+        db.unsubscribe(1);
+
+        db.set_client(2);
+        assert_eq!(db.count(), 0);
+        assert_eq!(run(&mut db, "SET b 2"), CommandResponse::Ok(String::new()));
+        assert!(db.is_dirty());
+    }
+
+    #[test]
+    fn reset_discards_the_callers_transaction() {
+        let mut db = Database::new();
+        run(&mut db, "SET kept 1");
+        run(&mut db, "SELECT 2");
+        run(&mut db, "BEGIN");
+        run(&mut db, "SET a 1");
+        assert_eq!(
+            run(&mut db, "RESET"),
+            CommandResponse::Ok("Session reset".into())
+        );
+
+        assert!(!db.in_transaction());
+        assert_eq!(db.selected(), 0);
+        assert_eq!(db.keys(), vec!["kept"]);
+        run(&mut db, "SELECT 2");
+        assert_eq!(db.count(), 0);
+    }
+
+    #[test]
+    fn reset_leaves_other_sessions_alone() {
+        let (sender_a, receiver_a) = std::sync::mpsc::channel();
+        let (sender_b, receiver_b) = std::sync::mpsc::channel();
+        let mut db = Database::new();
+        db.subscribe(1, sender_a);
+        db.subscribe(2, sender_b);
+
+        db.set_client(2);
+        run(&mut db, "WATCH w");
+        run(&mut db, "BEGIN");
+        run(&mut db, "SET t int 1");
+
+        db.set_client(1);
+        run(&mut db, "WATCH w");
+        run(&mut db, "RESET");
+
+        db.set_client(2);
+        assert_eq!(
+            run(&mut db, "COMMIT"),
+            CommandResponse::Ok("Transaction committed".into())
+        );
+        assert_eq!(db.get("t").and_then(DbValue::as_int), Some(1));
+
+        // Only client 1 stopped watching, and both channels still work.
+        run(&mut db, "SET w 1");
+        assert_eq!(
+            receiver_b.try_iter().collect::<Vec<String>>(),
+            vec!["WATCH w set"]
+        );
+        assert_eq!(receiver_a.try_iter().count(), 0);
+        db.set_client(1);
+        run(&mut db, "WATCH w");
+        run(&mut db, "DELETE w");
+        assert_eq!(
+            receiver_a.try_iter().collect::<Vec<String>>(),
+            vec!["WATCH w deleted"]
+        );
+    }
+
+    #[test]
+    fn watchers_get_a_line_for_each_change() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut db = Database::new();
+        db.subscribe(1, sender);
+        db.set_client(1);
+        assert_eq!(
+            run(&mut db, "WATCH k"),
+            CommandResponse::Ok("Watching k".into())
+        );
+        assert_eq!(
+            run(&mut db, "WATCH k"),
+            CommandResponse::Ok("Already watching k".into())
+        );
+
+        db.set_client(2);
+        run(&mut db, "SET k 1");
+        run(&mut db, "SET other 1");
+        run(&mut db, "INCR k");
+        run(&mut db, "DELETE k");
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<String>>(),
+            vec!["WATCH k set", "WATCH k set", "WATCH k deleted"]
+        );
+
+        // Closing the channel drops the watch on the next change.
+        drop(receiver);
+        run(&mut db, "SET k 2");
+        assert!(db.watchers.is_empty());
+    }
+
+    #[test]
+    fn load_replaces_the_current_keys() {
+        let path = temp_path("load-replace");
+        let mut saved = Database::new();
+        saved.set("new".into(), DbValue::from_i64(1));
+        saved.save(&path).unwrap();
+
+        let mut db = Database::new();
+        run(&mut db, "SET old 1");
+        run(&mut db, &format!("LOAD {}", path));
+        assert_eq!(db.keys(), vec!["new"]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn display_marks_corrupt_data() {
+        let value = DbValue {
+            typetag: DataType::Int,
+            data: vec![1, 2, 3],
+            expires_at: None,
+            ttl: None,
+        };
+        assert_eq!(value.to_string(), "<invalid int data (3 bytes)>");
+    }
+
+    #[test]
+    fn int_parsing_tells_range_from_garbage() {
+        assert_eq!(parse_int("-9223372036854775808"), Ok(i64::MIN));
+        assert_eq!(parse_int("9223372036854775807"), Ok(i64::MAX));
+        let out_of_range = Err("integer out of range for i64".to_string());
+        assert_eq!(parse_int("9223372036854775808"), out_of_range);
+        assert_eq!(parse_int("-9223372036854775809"), out_of_range);
+        for garbage in ["", "12a", "1.0", "--1", " 1"] {
+            assert_eq!(
+                parse_int(garbage),
+                Err("not a valid integer".to_string()),
+                "{:?}",
+                garbage
+            );
+        }
+    }
+
+    #[test]
+    fn uint_parsing_tells_range_from_garbage() {
+        assert_eq!(parse_uint("18446744073709551615"), Ok(u64::MAX));
+        assert_eq!(
+            parse_uint("18446744073709551616"),
+            Err("integer out of range for u64".to_string())
+        );
+        for garbage in ["-1", "", "0x10", "1e3"] {
+            assert_eq!(
+                parse_uint(garbage),
+                Err("not a valid unsigned integer".to_string()),
+                "{:?}",
+                garbage
+            );
+        }
+    }
+
+    #[test]
+    fn float_parsing_rejects_overflow_but_not_inf() {
+        assert_eq!(parse_float("1e308"), Ok(1e308));
+        assert_eq!(
+            parse_float("1e309"),
+            Err("float out of range for f64".to_string())
+        );
+        assert_eq!(
+            parse_float("-1e309"),
+            Err("float out of range for f64".to_string())
+        );
+        assert_eq!(parse_float("-inf"), Ok(f64::NEG_INFINITY));
+        assert_eq!(parse_float("abc"), Err("not a valid float".to_string()));
+    }
+
+    #[test]
+    fn estimated_size_grows_with_the_data() {
+        let mut db = Database::new();
+        assert_eq!(db.estimated_size(), 0);
+
+        db.set("k".into(), DbValue::from_str(&"x".repeat(1000)));
+        let one = db.estimated_size();
+        // The key twice and the value, plus some fixed overhead.
+        assert!((1002..1002 + 256).contains(&one), "{}", one);
+
+        db.set("j".into(), DbValue::from_str(&"x".repeat(1000)));
+        assert_eq!(db.estimated_size(), one * 2);
+        db.set("k".into(), DbValue::from_i64(1));
+        assert_eq!(db.estimated_size(), one * 2 - 992);
+    }
+
+    #[test]
+    fn human_size_picks_a_unit() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MB");
+    }
+
+    #[test]
+    fn last_save_updates_after_a_save() {
+        let path = temp_path("last-save");
+        let mut db = Database::new();
+        assert_eq!(db.last_save(), None);
+
+        let before = SystemTime::now();
+        run(&mut db, &format!("SAVE {}", path));
+        let saved = db.last_save().unwrap();
+        assert!(saved >= before);
+
+        // A failed save leaves it alone.
+        run(&mut db, &format!("SAVE {}", temp_path("no-such-dir/db")));
+        assert_eq!(db.last_save(), Some(saved));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn accessors_do_not_panic_on_mismatched_data() {
+        let value = |typetag: DataType, data: &[u8]| DbValue {
+            typetag,
+            data: data.to_vec(),
+            expires_at: None,
+            ttl: None,
+        };
+        for data in [&[][..], &[1, 2, 3], &[0; 9]] {
+            assert_eq!(value(DataType::Int, data).as_int(), None);
+            assert_eq!(value(DataType::Float, data).as_float(), None);
+            assert_eq!(value(DataType::UInt, data).as_u64(), None);
+            assert_eq!(value(DataType::DateTime, data).as_datetime(), None);
+        }
+        assert_eq!(value(DataType::Bool, &[1, 1]).as_bool(), None);
+        assert!(value(DataType::Array, &[42]).array_items().is_none());
+        assert_eq!(value(DataType::Hash, &[0, 0, 0, 9]).hash_fields(), None);
+        // Each accessor checks the tag, not just the length.
+        assert_eq!(value(DataType::String, &[0; 8]).as_int(), None);
+        assert_eq!(value(DataType::Int, b"text").as_string(), None);
+    }
+
+    #[test]
+    fn commands_report_mismatched_data() {
+        let mut db = Database::new();
+        db.set(
+            "k".into(),
+            DbValue {
+                typetag: DataType::Int,
+                data: vec![1],
+                expires_at: None,
+                ttl: None,
+            },
+        );
+        assert_eq!(
+            run(&mut db, "GET k"),
+            CommandResponse::Error("Corrupt value at k: invalid int data (1 bytes)".into())
+        );
+        assert_eq!(
+            run(&mut db, "INCR k"),
+            CommandResponse::Error("Value is not an integer".into())
+        );
+    }
+
+    // Old native-endian files are not supported; everything is big-endian.
+    #[test]
+    fn numbers_decode_from_big_endian_bytes() {
+        let value = |typetag: DataType, data: Vec<u8>| DbValue {
+            typetag,
+            data,
+            expires_at: None,
+            ttl: None,
+        };
+        assert_eq!(
+            value(DataType::Int, vec![0, 0, 0, 0, 0, 0, 1, 2]).as_int(),
+            Some(258)
+        );
+        assert_eq!(value(DataType::Int, vec![0xff; 8]).as_int(), Some(-1));
+        assert_eq!(
+            value(DataType::UInt, vec![1, 0, 0, 0, 0, 0, 0, 0]).as_u64(),
+            Some(1 << 56)
+        );
+        assert_eq!(
+            value(DataType::Float, vec![0x3f, 0xf8, 0, 0, 0, 0, 0, 0]).as_float(),
+            Some(1.5)
+        );
+        assert_eq!(DbValue::from_i64(258).data, vec![0, 0, 0, 0, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn infer_type_takes_the_first_match() {
+        for (text, expected) in [
+            ("true", DataType::Bool),
+            ("True", DataType::String),
+            ("-12", DataType::Int),
+            ("007", DataType::Int),
+            ("1.5", DataType::Float),
+            ("1e3", DataType::Float),
+            // Too large for an i64, and not written as a float.
+            ("99999999999999999999", DataType::String),
+            ("inf", DataType::String),
+            ("1e999", DataType::String),
+            ("1.2.3", DataType::String),
+        ] {
+            assert_eq!(infer_type(text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn load_refuses_files_from_a_newer_version() {
+        let path = temp_path("load-future");
+        std::fs::write(&path, [&b"PILL"[..], &[FORMAT_VERSION + 1]].concat()).unwrap();
+        let Err(e) = Database::load(&path) else {
+            panic!("loaded a future version");
+        };
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, &format!("LOAD {}", path)),
+            CommandResponse::Error(format!(
+                "unsupported database version {}",
+                FORMAT_VERSION + 1
+            ))
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn load_migrates_version_1_bools() {
+        let path = temp_path("load-v1");
+        let mut bytes = b"PILL\x01".to_vec();
+        bytes.extend([0, 0, 0, 1, b'b', DataType::Bool as u8, 0, 0, 0, 4]);
+        bytes.extend(b"true");
+        bytes.extend([0; 16]);
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut db = Database::load(&path).unwrap();
+        assert_eq!(db.get("b").map(|value| value.data.clone()), Some(vec![1]));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn persist_only_reports_keys_that_had_an_expiry() {
+        let (mut db, clock) = with_manual_clock();
+        assert_eq!(run(&mut db, "PERSIST missing"), value("0"));
+        run(&mut db, "SET k 1");
+        assert_eq!(run(&mut db, "PERSIST k"), value("0"));
+
+        run(&mut db, "EXPIRE k 5");
+        assert_eq!(run(&mut db, "PERSIST k"), value("1"));
+        assert_eq!(ttl(&mut db, "k"), value("-1"));
+        assert_eq!(run(&mut db, "PERSIST k"), value("0"));
+        // TOUCH has no window left to restart.
+        run(&mut db, "TOUCH k");
+        assert_eq!(ttl(&mut db, "k"), value("-1"));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(run(&mut db, "GET k"), value("k: 1"));
+    }
+
+    #[test]
+    fn import_csv_reads_rows_after_the_header() {
+        let mut db = Database::new();
+        let text = "key,type,value\nname,str,\"Smith, Jo\"\nage,int,42\n";
+        assert_eq!(db.import_csv(text).unwrap(), (2, Vec::new()));
+        assert_eq!(
+            db.get("name").and_then(DbValue::as_string),
+            Some("Smith, Jo")
+        );
+        assert_eq!(db.get("age").and_then(DbValue::as_int), Some(42));
+    }
+
+    #[test]
+    fn import_csv_skips_bad_rows() {
+        let mut db = Database::new();
+        let text = "a,str,ok\nb,colour,red\nc,int\n\" \",str,x\n";
+        let (imported, skipped) = db.import_csv(text).unwrap();
+        assert_eq!(imported, 1);
+        assert_eq!(skipped.len(), 3);
+        assert!(skipped[0].starts_with("row 2 (b): "), "{}", skipped[0]);
+        assert_eq!(skipped[1], "row 3: expected 3 fields, found 2");
+        assert_eq!(skipped[2], "row 4: invalid key");
+        assert_eq!(db.keys(), vec!["a"]);
+
+        assert!(db.import_csv("a,str,\"open\n").is_err());
+    }
+
+    #[test]
+    fn csv_export_imports_back_unchanged() {
+        let mut db = Database::new();
+        db.set("s".into(), DbValue::from_str("a, \"quoted\"\nline"));
+        db.set("i".into(), DbValue::from_i64(-7));
+        db.set("f".into(), DbValue::from_f64(2.5));
+        db.set("u".into(), DbValue::from_u64(u64::MAX));
+        db.set("b".into(), DbValue::from_bool(true));
+        db.set("x".into(), DbValue::from_bytes(&[0, 0xff]));
+        db.set("n".into(), DbValue::null());
+        db.set("d".into(), DbValue::from_datetime(1_700_000_000));
+        db.set("key, with comma".into(), DbValue::from_str("v"));
+        db.set("list".into(), DbValue::from_array(&[DbValue::from_i64(1)]));
+
+        let (text, skipped) = db.export_csv();
+        assert_eq!(skipped, vec!["list: array values need EXPORT".to_string()]);
+
+        let mut copy = Database::new();
+        let (imported, skipped) = copy.import_csv(&text).unwrap();
+        assert!(skipped.is_empty(), "{:?}", skipped);
+        assert_eq!(imported, 9);
+        for key in ["s", "i", "f", "u", "b", "x", "n", "d", "key, with comma"] {
+            let original = db.get(key).unwrap().clone();
+            let loaded = copy.get(key).unwrap();
+            assert_eq!(original.typetag, loaded.typetag, "{}", key);
+            assert_eq!(original.data, loaded.data, "{}", key);
+        }
+    }
+
+    #[test]
+    fn growing_a_value_respects_max_value_bytes() {
+        let mut db = Database::new();
+        db.set_max_value_bytes(Some(5));
+        let too_large = CommandResponse::Error("value too large".into());
+
+        run(&mut db, "SET s str abcd");
+        assert_eq!(run(&mut db, "APPEND s e"), value("5"));
+        assert_eq!(run(&mut db, "APPEND s f"), too_large);
+        assert_eq!(run(&mut db, "SETRANGE s 5 g"), too_large);
+        assert_eq!(run(&mut db, "SETRANGE t 4 x"), value("5"));
+        assert_eq!(run(&mut db, "GET s"), value("s: abcde"));
+
+        // Arrays count their type byte and length prefixes too.
+        assert_eq!(run(&mut db, "RPUSH l str ab"), too_large);
+        assert!(!db.exists("l"));
+        assert_eq!(run(&mut db, "HSET h field long-value"), too_large);
+        assert!(!db.exists("h"));
+    }
+
+    #[test]
+    fn bools_are_one_canonical_byte() {
+        assert_eq!(DbValue::from_bool(true).data, vec![1]);
+        assert_eq!(DbValue::from_bool(false).data, vec![0]);
+        assert_eq!(DbValue::from_bool(false).as_bool(), Some(false));
+        assert_eq!(DbValue::from_bool(true).as_bool(), Some(true));
+
+        let mut db = Database::new();
+        db.set("off".into(), DbValue::from_bool(false));
+        assert_eq!(run(&mut db, "GET off"), value("off: false"));
+        run(&mut db, "SET on bool true");
+        assert_eq!(run(&mut db, "GET on"), value("on: true"));
+        assert_eq!(db.get("on").map(|value| value.data.clone()), Some(vec![1]));
+    }
+
+    #[test]
+    fn watches_only_cover_their_own_database() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut db = Database::new();
+        db.subscribe(1, sender);
+        db.set_client(1);
+        run(&mut db, "WATCH k");
+
+        db.set_client(2);
+        run(&mut db, "SELECT 3");
+        run(&mut db, "SET k 1");
+        assert_eq!(receiver.try_iter().count(), 0);
+        run(&mut db, "SELECT 0");
+        run(&mut db, "SET k 1");
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec!["WATCH k set"]);
+    }
+
+    #[test]
+    fn move_tells_watchers_on_both_sides() {
+        let (source, from_source) = std::sync::mpsc::channel();
+        let (target, from_target) = std::sync::mpsc::channel();
+        let mut db = Database::new();
+        db.subscribe(1, source);
+        db.subscribe(2, target);
+        db.set_client(1);
+        run(&mut db, "WATCH k");
+        db.set_client(2);
+        run(&mut db, "SELECT 1");
+        run(&mut db, "WATCH k");
+
+        db.set_client(3);
+        run(&mut db, "SET k 1");
+        from_source.try_iter().for_each(drop);
+        assert_eq!(run(&mut db, "MOVE k 1"), value("1"));
+        assert_eq!(
+            from_source.try_iter().collect::<Vec<_>>(),
+            vec!["WATCH k deleted"]
+        );
+        assert_eq!(
+            from_target.try_iter().collect::<Vec<_>>(),
+            vec!["WATCH k set"]
+        );
+    }
+
+    #[test]
+    fn load_tells_watchers_what_changed() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut db = Database::new();
+        db.subscribe(1, sender);
+        db.set_client(1);
+        for key in ["changed", "same", "gone", "new", "never"] {
+            run(&mut db, &format!("WATCH {}", key));
+        }
+        db.set_client(2);
+        run(&mut db, "SET changed 1");
+        run(&mut db, "SET same 1");
+        run(&mut db, "SET gone 1");
+        receiver.try_iter().for_each(drop);
+
+        let mut loaded = Database::new();
+        loaded.set("changed".into(), DbValue::from_i64(2));
+        loaded.set("same".into(), DbValue::from_i64(1));
+        loaded.set("new".into(), DbValue::from_i64(1));
+        db.replace(loaded);
+
+        let mut events = receiver.try_iter().collect::<Vec<_>>();
+        events.sort();
+        assert_eq!(
+            events,
+            vec!["WATCH changed set", "WATCH gone deleted", "WATCH new set"]
+        );
+    }
+}
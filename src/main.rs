@@ -1,202 +1,646 @@
-use std::{collections::HashMap, io, str, u8};
+use pillsdb::{
+    Autosave, ClientId, CommandResponse, Database, History, canonical_command, decode_line,
+    format_datetime, handle_command, human_size, install_signal_handlers, quote_line,
+    shutdown_requested, tokenize,
+};
+use std::{
+    env,
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, IsTerminal, Write},
+    net::{TcpListener, TcpStream},
+    process::{self, ExitCode},
+    sync::{
+        Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-#[derive(PartialEq, Debug, Clone)]
-enum DataType {
-    String = 0,
-    Int = 1,
-    Float = 2,
-    Bool = 3,
+// How responses are written, picked with --format.
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Plain,
+    Json,
 }
 
-#[derive(Debug)]
-struct DbValue {
-    typetag: DataType,
-    data: Vec<u8>,
+impl OutputFormat {
+    fn render(self, response: &CommandResponse) -> String {
+        match self {
+            OutputFormat::Plain => response.to_string(),
+            OutputFormat::Json => response.to_json(),
+        }
+    }
+}
+
+// Startup flags.
+struct Options {
+    // --server <addr>: serve clients over TCP instead of reading stdin.
+    server: Option<String>,
+    // --client-timeout <secs>: drop a server client that sends nothing for
+    // this long.
+    client_timeout: Option<Duration>,
+    // --autosave <path>: write the database there after changes.
+    autosave: Option<String>,
+    // --autosave-interval <secs>: minimum time between autosave writes.
+    autosave_interval: Duration,
+    // --wal <path>: replay this log on startup and append mutations to it.
+    wal: Option<String>,
+    // --quiet: no prompt and no OK confirmations in the REPL.
+    quiet: bool,
+    // --history <path>: where REPL history is kept. Defaults to
+    // ~/.pillsdb_history for interactive sessions.
+    history: Option<String>,
+    // --history-size <n>: how many lines of history to keep.
+    history_size: usize,
+    // --case-insensitive: treat `Foo` and `foo` as the same key.
+    case_insensitive: bool,
+    // --max-keys <n>: evict the least recently used key beyond this many.
+    max_keys: Option<usize>,
+    // --load <path>: start from a file written by SAVE.
+    load: Option<String>,
+    // --readonly: reject every command that would change the data.
+    readonly: bool,
+    // --max-value-bytes <n>: refuse writes that leave a value larger than this.
+    max_value_bytes: Option<usize>,
+    // --strict-types: refuse to change the type of an existing key.
+    strict_types: bool,
+    // --log <path>: append every command and its outcome to this file.
+    log: Option<String>,
+    // --format plain|json: how responses are written.
+    format: OutputFormat,
 }
 
-impl DbValue {
-    // == To set data types easily. ==
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        server: None,
+        client_timeout: None,
+        autosave: None,
+        autosave_interval: Duration::from_secs(1),
+        wal: None,
+        quiet: false,
+        history: None,
+        history_size: 500,
+        case_insensitive: false,
+        max_keys: None,
+        load: None,
+        readonly: false,
+        max_value_bytes: None,
+        strict_types: false,
+        format: OutputFormat::Plain,
+        log: None,
+    };
+    let mut args = args.iter();
 
-    fn from_str(s: &str) -> Self {
-        DbValue {
-            typetag: DataType::String,
-            data: s.as_bytes().to_vec(),
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--server" => {
+                let addr = args.next().ok_or("--server needs an address")?;
+                options.server = Some(addr.clone());
+            }
+            "--client-timeout" => {
+                let secs = args
+                    .next()
+                    .and_then(|secs| secs.parse::<f64>().ok())
+                    .filter(|secs| *secs > 0.0)
+                    .ok_or("--client-timeout needs a positive number of seconds")?;
+                options.client_timeout = Some(Duration::from_secs_f64(secs));
+            }
+            "--autosave" => {
+                let path = args.next().ok_or("--autosave needs a path")?;
+                options.autosave = Some(path.clone());
+            }
+            "--autosave-interval" => {
+                let secs = args
+                    .next()
+                    .and_then(|secs| secs.parse::<f64>().ok())
+                    .filter(|secs| *secs >= 0.0)
+                    .ok_or("--autosave-interval needs a number of seconds")?;
+                options.autosave_interval = Duration::from_secs_f64(secs);
+            }
+            "--load" => {
+                let path = args.next().ok_or("--load needs a path")?;
+                options.load = Some(path.clone());
+            }
+            "--wal" => {
+                let path = args.next().ok_or("--wal needs a path")?;
+                options.wal = Some(path.clone());
+            }
+            "--quiet" => options.quiet = true,
+            "--case-insensitive" => options.case_insensitive = true,
+            "--readonly" => options.readonly = true,
+            "--strict-types" => options.strict_types = true,
+            "--max-keys" => {
+                let max = args
+                    .next()
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|n| *n > 0)
+                    .ok_or("--max-keys needs a positive number")?;
+                options.max_keys = Some(max);
+            }
+            "--format" => {
+                options.format = match args.next().map(String::as_str) {
+                    Some("plain") => OutputFormat::Plain,
+                    Some("json") => OutputFormat::Json,
+                    _ => return Err("--format needs plain or json".to_string()),
+                };
+            }
+            "--max-value-bytes" => {
+                let max = args
+                    .next()
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .ok_or("--max-value-bytes needs a number of bytes")?;
+                options.max_value_bytes = Some(max);
+            }
+            "--log" => {
+                let path = args.next().ok_or("--log needs a path")?;
+                options.log = Some(path.clone());
+            }
+            "--history" => {
+                let path = args.next().ok_or("--history needs a path")?;
+                options.history = Some(path.clone());
+            }
+            "--history-size" => {
+                options.history_size = args
+                    .next()
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .ok_or("--history-size needs a number of lines")?;
+            }
+            _ => return Err(format!("Unknown argument: {}", arg)),
         }
     }
 
-    fn from_i64(i: i64) -> Self {
-        DbValue {
-            typetag: DataType::Int,
-            data: i.to_ne_bytes().to_vec(),
-        }
+    Ok(options)
+}
+
+// State kept between commands, shared by the REPL and the server.
+struct App {
+    db: Database,
+    autosave: Option<Autosave>,
+    // Last id handed out by `connect`.
+    last_client: ClientId,
+    format: OutputFormat,
+    // --log: the audit log, opened for appending.
+    log: Option<File>,
+}
+
+impl App {
+    // Registers a new client and returns where its WATCH notifications
+    // arrive.
+    fn connect(&mut self) -> (ClientId, Receiver<String>) {
+        self.last_client += 1;
+        let (sender, receiver) = mpsc::channel();
+        self.db.subscribe(self.last_client, sender);
+        (self.last_client, receiver)
     }
 
-    fn from_f64(f: f64) -> Self {
-        DbValue {
-            typetag: DataType::Float,
-            data: f.to_ne_bytes().to_vec(),
-        }
+    fn disconnect(&mut self, client: ClientId) {
+        self.db.unsubscribe(client);
     }
 
-    fn from_bool(b: bool) -> Self {
-        DbValue {
-            typetag: DataType::Bool,
-            data: if b { "true" } else { "false" }.as_bytes().to_vec(),
+    fn run(&mut self, client: ClientId, input: &[&str]) -> CommandResponse {
+        let response = self.execute(client, input);
+
+        if let Some(log) = &mut self.log
+            && let Err(e) = write_log(log, client, input, &response)
+        {
+            println!("Failed to write log: {}", e);
         }
+
+        response
     }
 
-    //todo --> fn from_hex(hex: &str) -> Self {}
+    fn execute(&mut self, client: ClientId, input: &[&str]) -> CommandResponse {
+        self.db.set_client(client);
+        // STATUS reports on the front end as well as the database.
+        if canonical_command(input[0]) == "STATUS" {
+            return self.status();
+        }
 
-    // == To get data types easily. ==
+        let response = handle_command(&mut self.db, input);
 
-    fn as_string(&self) -> Option<&str> {
-        if self.typetag == DataType::String {
-            Some(str::from_utf8(&self.data).unwrap())
-        } else {
-            None
+        if let Some(autosave) = &mut self.autosave
+            && let Err(e) = autosave.tick(&mut self.db)
+        {
+            println!("Autosave failed: {}", e);
         }
-    }
 
-    fn as_int(&self) -> Option<i64> {
-        if self.typetag == DataType::Int && self.data.len() == 8 {
-            // i64 and f64 are always 8 bytes.
-            Some(i64::from_ne_bytes(self.data[..8].try_into().unwrap()))
-        } else {
-            None
-        }
+        response
     }
 
-    fn as_float(&self) -> Option<f64> {
-        if self.typetag == DataType::Float && self.data.len() == 8 {
-            // i64 and f64 are always 8 bytes.
-            Some(f64::from_ne_bytes(self.data[..8].try_into().unwrap()))
-        } else {
-            None
-        }
+    fn status(&self) -> CommandResponse {
+        let autosave = match &self.autosave {
+            Some(autosave) => format!(
+                "on, every {}s to {}",
+                autosave.interval().as_secs_f64(),
+                autosave.path()
+            ),
+            None => "off".to_string(),
+        };
+        let last_save = match self.db.last_save() {
+            Some(at) => at
+                .duration_since(UNIX_EPOCH)
+                .map(|since| format_datetime(since.as_secs() as i64))
+                .unwrap_or_else(|_| "unknown".to_string()),
+            None => "never".to_string(),
+        };
+
+        CommandResponse::Value(
+            [
+                format!("keys: {}", self.db.count()),
+                format!("memory: {}", human_size(self.db.estimated_size())),
+                format!("autosave: {}", autosave),
+                format!("last save: {}", last_save),
+            ]
+            .join("\n"),
+        )
     }
 
-    fn as_bool(&self) -> Option<bool> {
-        if self.typetag == DataType::Bool && !self.data.is_empty() {
-            Some(self.data[0] != 0)
-        } else {
-            None
+    // Writes anything the autosave interval held back.
+    fn shutdown(&mut self) {
+        if let Some(autosave) = &mut self.autosave
+            && let Err(e) = autosave.flush(&mut self.db)
+        {
+            println!("Autosave failed: {}", e);
         }
     }
 }
 
-struct Database {
-    db: HashMap<String, DbValue>,
+// One line per command: when, who, what, and whether it worked. Unlike the
+// WAL this is only for people to read, so errors are cut to their first
+// line and values are left out.
+fn write_log(
+    file: &mut File,
+    client: ClientId,
+    input: &[&str],
+    response: &CommandResponse,
+) -> io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or_default();
+    let status = match response {
+        CommandResponse::Error(msg) => format!("ERR {}", msg.lines().next().unwrap_or_default()),
+        _ => "OK".to_string(),
+    };
+    writeln!(
+        file,
+        "{} client {}: {} -> {}",
+        format_datetime(now),
+        client,
+        quote_line(input),
+        status
+    )?;
+    // So the log survives a crash.
+    file.flush()
 }
 
-impl Database {
-    fn new() -> Self {
-        Database { db: HashMap::new() }
-    }
+fn is_quit(input: &[&str]) -> bool {
+    canonical_command(input[0]) == "QUIT"
+}
 
-    fn get(&self, key: &str) -> Option<&DbValue> {
-        self.db.get(key)
-    }
+// Piped input only records history when a file was asked for explicitly.
+fn open_history(options: &Options, interactive: bool) -> History {
+    let path = options.history.clone().or_else(|| {
+        env::var("HOME")
+            .ok()
+            .filter(|_| interactive)
+            .map(|home| format!("{}/.pillsdb_history", home))
+    });
 
-    fn set(&mut self, key: String, value: DbValue) {
-        self.db.insert(key, value);
+    match &path {
+        Some(path) => History::load(path, options.history_size).unwrap_or_else(|e| {
+            println!("Failed to read history {}: {}", path, e);
+            History::new(None, options.history_size)
+        }),
+        None => History::new(None, options.history_size),
     }
 }
 
-fn main() {
-    let mut db = Database::new();
+fn run_repl(app: &Mutex<App>, options: &Options) {
+    let mut stdin = io::stdin().lock();
+    let interactive = stdin.is_terminal();
+    // Piped input gets no prompt so scripted output stays clean.
+    let prompt = !options.quiet && interactive;
+    let mut history = open_history(options, interactive);
+    let (client, notifications) = lock(app).connect();
+    let format = lock(app).format;
 
     loop {
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
-        let input = input.trim().split(" ").collect::<Vec<&str>>();
+        if prompt {
+            print!("> ");
+            let _ = io::stdout().flush();
+        }
+
+        let mut buf = Vec::new();
+        match stdin.read_until(b'\n', &mut buf) {
+            // Stdin was closed.
+            Ok(0) => break,
+            Ok(_) => {}
+            // An interrupted read only loses that line.
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                println!("Failed to read line: {}", e);
+                continue;
+            }
+            Err(e) => {
+                println!("Failed to read line: {}", e);
+                break;
+            }
+        }
+
+        let line = match decode_line(&buf) {
+            Ok(line) => line,
+            Err(e) => {
+                println!("{}", format.render(&e));
+                continue;
+            }
+        };
+        let tokens = match tokenize(line) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                println!("{}", format.render(&e));
+                continue;
+            }
+        };
+        let input = tokens.iter().map(String::as_str).collect::<Vec<&str>>();
 
         if input.is_empty() {
             continue;
         }
 
-        match input[0].to_uppercase().as_str() {
-            // <== INPUT[0] = COMMAND
-            "GET" => {
-                if input.len() < 2 {
-                    println!("Usage: GET <key>");
-                    continue;
-                }
+        if let Err(e) = history.push(line) {
+            println!("Failed to write history: {}", e);
+        }
+
+        if is_quit(&input) {
+            break;
+        }
+
+        if canonical_command(input[0]) == "HISTORY" {
+            let listing = CommandResponse::Value(history.listing());
+            println!("{}", format.render(&listing));
+            continue;
+        }
+
+        let response = lock(app).run(client, &input);
+        if !options.quiet || !matches!(response, CommandResponse::Ok(_)) {
+            println!("{}", format.render(&response));
+        }
+
+        for notification in notifications.try_iter() {
+            println!("{}", format.render(&CommandResponse::Value(notification)));
+        }
+    }
+
+    lock(app).disconnect(client);
+
+    if let Err(e) = history.save() {
+        println!("Failed to write history: {}", e);
+    }
+}
+
+// Locks the shared state. A thread that panicked mid-command can't
+// leave the data half-written in a way the others would trip over, so a
+// poisoned lock is still used.
+fn lock(app: &Mutex<App>) -> MutexGuard<'_, App> {
+    app.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Serves each client on its own thread. Commands take turns on one lock;
+// even reads need it, since GET updates the LRU order and drops expired
+// keys.
+fn run_server(app: &Mutex<App>, addr: &str, timeout: Option<Duration>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("Listening on {}", listener.local_addr()?);
 
-                match db.get(input[1]) {
-                    Some(value) => {
-                        println!("{:?}", value);
-                        match value.typetag {
-                            DataType::String => {
-                                println!("{}: {}", input[1], value.as_string().unwrap())
-                            }
-                            DataType::Int => println!("{}: {}", input[1], value.as_int().unwrap()),
-                            DataType::Float => {
-                                println!("{}: {}", input[1], value.as_float().unwrap())
-                            }
-                            DataType::Bool => {
-                                println!("{}: {}", input[1], value.as_bool().unwrap())
-                            }
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(move || {
+                        if let Err(e) = stream
+                            .set_read_timeout(timeout)
+                            .and_then(|()| serve_client(app, stream))
+                        {
+                            println!("Client error: {}", e);
                         }
-                    }
-                    None => println!("Key not found"),
+                    });
                 }
+                Err(e) => println!("Failed to accept connection: {}", e),
             }
-            "SET" => {
-                if input.len() < 3 {
-                    println!("Usage: SET <key> <type> <value>");
-                    println!("Types: str, int, float, bool");
-                    continue;
-                };
+        }
+    });
 
-                let key = input[1].to_string();
-                let value_type = input[2].to_lowercase();
-                let value_str = input[3..].join(" ");
-
-                let value = match value_type.as_str() {
-                    "str" | "string" => DbValue::from_str(&value_str),
-                    "int" | "i64" => match value_str.parse::<i64>() {
-                        Ok(i) => DbValue::from_i64(i),
-                        Err(_) => {
-                            println!("Invalid integer value");
-                            continue;
-                        }
-                    },
-                    "float" | "f64" => match value_str.parse::<f64>() {
-                        Ok(f) => DbValue::from_f64(f),
-                        Err(_) => {
-                            println!("Invalid float value");
-                            continue;
-                        }
-                    },
-                    "bool" => match value_str.parse::<bool>() {
-                        Ok(b) => DbValue::from_bool(b),
-                        Err(_) => {
-                            println!("Invalid boolean value (use 'true' or 'false')");
-                            continue;
-                        }
-                    },
-                    _ => {
-                        println!("Invalid type. Use: str, int, float, bool");
-                        continue;
-                    }
-                };
-                db.set(key, value);
-                println!("SET successful");
-            }
-            "DEBUG" => {
-                for (_key, value) in &db.db {
-                    let mut hex_string = format!("{:02x}", value.typetag.clone() as u8);
-                    for byte in &value.data {
-                        hex_string.push_str(&format!("{:02x}", byte));
-                    }
-                    println!("hex: {}", hex_string);
+    Ok(())
+}
+
+// Responses and WATCH notifications share the socket, so each line is
+// written under `writer`. Notifications go out as soon as they arrive, from
+// a thread of their own, so an idle watcher still gets them.
+fn serve_client(app: &Mutex<App>, stream: TcpStream) -> io::Result<()> {
+    let writer = Mutex::new(stream.try_clone()?);
+    let (client, notifications, format) = {
+        let mut app = lock(app);
+        let (client, notifications) = app.connect();
+        (client, notifications, app.format)
+    };
+
+    let writer = &writer;
+    thread::scope(|scope| {
+        // Ends when `disconnect` drops the sending side.
+        scope.spawn(move || {
+            for notification in notifications {
+                let notification = CommandResponse::Value(notification);
+                let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+                if writeln!(writer, "{}", format.render(&notification)).is_err() {
+                    break;
                 }
             }
-            _ => {
-                println!("Unknown command")
+        });
+
+        let result = serve_connection(app, client, writer, stream);
+        lock(app).disconnect(client);
+        result
+    })
+}
+
+fn serve_connection(
+    app: &Mutex<App>,
+    client: ClientId,
+    writer: &Mutex<TcpStream>,
+    stream: TcpStream,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let format = lock(app).format;
+
+    loop {
+        let mut buf = Vec::new();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => break,
+            Ok(_) => {}
+            // --client-timeout ran out; which kind is reported depends on
+            // the platform.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                println!("Client {} timed out, disconnecting", client);
+                break;
             }
+            Err(e) => return Err(e),
+        }
+
+        // Taken before the command runs, so the notifications it causes come
+        // after its response.
+        let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let tokens = match decode_line(&buf).and_then(tokenize) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                writeln!(writer, "{}", format.render(&e))?;
+                continue;
+            }
+        };
+        let input = tokens.iter().map(String::as_str).collect::<Vec<&str>>();
+
+        if input.is_empty() {
+            continue;
+        }
+
+        if is_quit(&input) {
+            break;
+        }
+
+        // Held only for the command, not while writing to a slow client.
+        let response = lock(app).run(client, &input);
+        writeln!(writer, "{}", format.render(&response))?;
+    }
+
+    Ok(())
+}
+
+// Polls for SIGINT and SIGTERM until `finished` is set. On a signal, waits
+// for the running command, writes any pending autosave and exits.
+fn watch_signals(app: &Mutex<App>, finished: &AtomicBool) {
+    while !finished.load(Ordering::Relaxed) {
+        if shutdown_requested() {
+            println!("Shutting down");
+            lock(app).shutdown();
+            process::exit(0);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// Everything after argument parsing. Startup failures come back as errors
+// so main can exit non-zero.
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = parse_args(args)?;
+
+    let mut db = Database::new();
+    db.set_case_insensitive(options.case_insensitive);
+    db.set_max_keys(options.max_keys);
+    db.set_max_value_bytes(options.max_value_bytes);
+    db.set_strict_types(options.strict_types);
+    if let Some(path) = &options.load {
+        let loaded = Database::load(path).map_err(|e| format!("Failed to load {}: {}", path, e))?;
+        for warning in db.replace(loaded) {
+            println!("Warning: skipped {}", warning);
+        }
+    }
+    if let Some(path) = &options.wal {
+        let replayed = db
+            .replay_wal(path)
+            .map_err(|e| format!("Failed to replay WAL {}: {}", path, e))?;
+        println!("Replayed {} commands from {}", replayed, path);
+    }
+    // Set last so the WAL replay above can still apply its changes.
+    db.set_readonly(options.readonly);
+
+    let app = App {
+        db,
+        autosave: options
+            .autosave
+            .as_deref()
+            .map(|path| Autosave::new(path, options.autosave_interval)),
+        last_client: 0,
+        format: options.format,
+        log: match &options.log {
+            Some(path) => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("Failed to open log {}: {}", path, e))?,
+            ),
+            None => None,
+        },
+    };
+
+    let app = Mutex::new(app);
+    install_signal_handlers();
+    let finished = AtomicBool::new(false);
+
+    let result = thread::scope(|scope| {
+        scope.spawn(|| watch_signals(&app, &finished));
+        let result = match &options.server {
+            Some(addr) => run_server(&app, addr, options.client_timeout)
+                .map_err(|e| format!("Server error: {}", e)),
+            None => {
+                run_repl(&app, &options);
+                Ok(())
+            }
+        };
+        finished.store(true, Ordering::Relaxed);
+        result
+    });
+
+    lock(&app).shutdown();
+    Ok(result?)
+}
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn a_bad_load_path_is_an_error() {
+        let e = run(&args(&["--load", "/nonexistent/pillsdb.db"])).unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with("Failed to load /nonexistent/pillsdb.db"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        for list in [
+            &["--bogus"][..],
+            &["--server"],
+            &["--max-keys", "0"],
+            &["--format", "xml"],
+        ] {
+            assert!(run(&args(list)).is_err(), "{:?}", list);
         }
     }
 }
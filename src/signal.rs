@@ -0,0 +1,37 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set by SIGINT or SIGTERM. The handler does nothing else, since almost
+// nothing is safe to call from inside one; the front end polls the flag and
+// shuts down properly.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+mod ffi {
+    pub const SIGINT: i32 = 2;
+    pub const SIGTERM: i32 = 15;
+
+    unsafe extern "C" {
+        pub fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_signum: i32) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+// Without this the default handlers kill the process on the spot. Does
+// nothing on platforms without Unix signals.
+pub fn install_signal_handlers() {
+    #[cfg(unix)]
+    // SAFETY: `on_signal` only stores to an atomic, which is
+    // async-signal-safe.
+    unsafe {
+        ffi::signal(ffi::SIGINT, on_signal);
+        ffi::signal(ffi::SIGTERM, on_signal);
+    }
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
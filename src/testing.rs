@@ -0,0 +1,9 @@
+// Helpers shared by the unit tests.
+
+// A path in the temp dir, unique to this process and test.
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("pillsdb-{}-{}", std::process::id(), name))
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,37 @@
+// Uses the database directly, without the REPL or the server.
+
+use pillsdb::{CommandResponse, DataType, Database, DbValue, handle_command};
+
+#[test]
+fn set_get_and_delete() {
+    let mut db = Database::new();
+    db.set("greeting".to_string(), DbValue::from_str("hello"));
+    db.set("answer".to_string(), DbValue::from_i64(42));
+
+    assert_eq!(
+        db.get("greeting").and_then(DbValue::as_string),
+        Some("hello")
+    );
+    assert_eq!(
+        db.get("answer").map(|value| &value.typetag),
+        Some(&DataType::Int)
+    );
+    assert_eq!(db.get("answer").and_then(DbValue::as_int), Some(42));
+
+    assert!(db.delete("greeting").is_some());
+    assert!(db.get("greeting").is_none());
+    assert!(db.delete("greeting").is_none());
+}
+
+#[test]
+fn commands_run_without_a_front_end() {
+    let mut db = Database::new();
+    assert_eq!(
+        handle_command(&mut db, &["SET", "k", "int", "5"]),
+        CommandResponse::Ok(String::new())
+    );
+    assert_eq!(
+        handle_command(&mut db, &["GET", "k"]),
+        CommandResponse::Value("k: 5".to_string())
+    );
+}
@@ -0,0 +1,167 @@
+// Drives the REPL binary through piped stdin.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Runs the binary with `input` on stdin, then closes it. Panics if the
+// process is still running after a few seconds.
+fn run_repl(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tests"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Dropped at the end of the statement, which closes stdin.
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("REPL still running after stdin closed");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn end_of_input_ends_the_session() {
+    let output = run_repl(&[], "SET k 1\nGET k\n");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "OK\nk: 1\n");
+}
+
+#[test]
+fn quit_and_exit_stop_reading() {
+    for quit in ["QUIT", "exit"] {
+        let output = run_repl(&[], &format!("SET k 1\n{}\nGET k\n", quit));
+        assert!(output.status.success());
+        assert_eq!(stdout(&output), "OK\n", "{}", quit);
+    }
+}
+
+#[test]
+fn quit_writes_the_autosave_file() {
+    let path = std::env::temp_dir().join(format!("pillsdb-{}-quit-autosave", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+
+    let args = ["--autosave", path, "--autosave-interval", "3600"];
+    let output = run_repl(&args, "SET k 1\nSET j 2\nQUIT\n");
+    assert!(output.status.success());
+
+    let output = run_repl(&["--load", path], "COUNT\n");
+    assert_eq!(stdout(&output), "2\n");
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn closed_stdin_ends_the_loop() {
+    let output = run_repl(&[], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+
+    // The last line still runs without its newline.
+    let output = run_repl(&[], "SET k 1\n\n   \nGET k");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "OK\nk: 1\n");
+}
+
+#[test]
+fn piped_input_gets_no_prompt() {
+    let output = run_repl(&[], "PING\n");
+    assert_eq!(stdout(&output), "PONG\n");
+}
+
+#[test]
+fn quiet_leaves_out_ok_replies() {
+    let output = run_repl(&["--quiet"], "SET k 1\nGET k\nGET missing\n");
+    assert_eq!(stdout(&output), "k: 1\nERR Key not found\n");
+}
+
+#[test]
+fn comments_and_blank_lines_are_skipped() {
+    let script = "# set things up\n\nSET k 1\n   # indented comment\n\t\nGET k\n#GET k\n";
+    let output = run_repl(&[], script);
+    assert_eq!(stdout(&output), "OK\nk: 1\n");
+}
+
+#[test]
+fn startup_failures_exit_non_zero() {
+    let output = run_repl(&["--load", "/nonexistent/pillsdb.db"], "");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Failed to load"), "{}", stderr);
+}
+
+#[test]
+fn json_format_writes_one_object_per_reply() {
+    let output = run_repl(&["--format", "json"], "SET k 1\nGET k\nGET missing\n");
+    assert_eq!(
+        stdout(&output),
+        "{\"ok\":true}\n{\"ok\":true,\"value\":\"k: 1\"}\n{\"ok\":false,\"error\":\"Key not found\"}\n"
+    );
+}
+
+#[test]
+fn log_records_each_command_and_its_outcome() {
+    let path = std::env::temp_dir().join(format!("pillsdb-{}-command-log", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+
+    let input = "SET k str \"a b\"\nGET k\nGET missing\n";
+    assert!(run_repl(&["--log", path], input).status.success());
+
+    let log = std::fs::read_to_string(path).unwrap();
+    let lines = log
+        .lines()
+        .map(|line| {
+            let (when, rest) = line.split_once(' ').unwrap();
+            assert!(when.len() == 20 && when.ends_with('Z'), "{}", when);
+            rest
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![
+            "client 1: SET k str \"a b\" -> OK",
+            "client 1: GET k -> OK",
+            "client 1: GET missing -> ERR Key not found",
+        ]
+    );
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn a_save_elsewhere_still_autosaves_on_exit() {
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("pillsdb-{}-exit-autosave", std::process::id()));
+    let other = dir.join(format!("pillsdb-{}-exit-other", std::process::id()));
+    let (path, other) = (path.to_str().unwrap(), other.to_str().unwrap());
+    let _ = std::fs::remove_file(path);
+
+    let args = ["--autosave", path, "--autosave-interval", "3600"];
+    let input = format!("SET a 1\nSET b 2\nSAVE {}\n", other);
+    assert!(run_repl(&args, &input).status.success());
+
+    let output = run_repl(&["--load", path], "GET b\n");
+    assert_eq!(stdout(&output), "b: 2\n");
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(other);
+}
@@ -0,0 +1,215 @@
+// Runs the binary with --server and talks to it over TCP.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    process::{Child, ChildStdout, Command, Stdio},
+    time::Duration,
+};
+
+// The server process, killed when dropped.
+struct Server {
+    child: Child,
+    addr: String,
+    // Kept open so later output doesn't hit a closed pipe.
+    _stdout: BufReader<ChildStdout>,
+}
+
+impl Server {
+    // Binds to a free port and waits for the `Listening on` line.
+    fn start(args: &[&str]) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_tests"))
+            .args(["--server", "127.0.0.1:0"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        let addr = line
+            .trim()
+            .strip_prefix("Listening on ")
+            .unwrap_or_else(|| panic!("unexpected first line: {:?}", line))
+            .to_string();
+        Server {
+            child,
+            addr,
+            _stdout: stdout,
+        }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(&self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    fn send(&mut self, line: &str) -> String {
+        writeln!(self.stream, "{}", line).unwrap();
+        self.read_line()
+    }
+
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line.trim_end().to_string()
+    }
+}
+
+#[test]
+fn set_and_get_over_tcp() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    assert_eq!(client.send("SET k str hello world"), "OK");
+    assert_eq!(client.send("GET k"), "k: hello world");
+    assert_eq!(client.send("GET missing"), "ERR Key not found");
+    assert_eq!(client.send("NOPE"), "ERR Unknown command");
+}
+
+#[test]
+fn later_clients_see_earlier_writes() {
+    let server = Server::start(&[]);
+    let mut first = server.connect();
+    assert_eq!(first.send("SET k 1"), "OK");
+    writeln!(first.stream, "QUIT").unwrap();
+    // QUIT closes the connection without a reply.
+    assert_eq!(first.read_line(), "");
+
+    let mut second = server.connect();
+    assert_eq!(second.send("GET k"), "k: 1");
+}
+
+#[test]
+fn invalid_utf8_gets_an_error_and_the_connection_stays_up() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    client.stream.write_all(b"SET k \xff\xfe\n").unwrap();
+    assert_eq!(client.read_line(), "ERR invalid utf-8 in command");
+    assert_eq!(client.send("GET k"), "ERR Key not found");
+    assert_eq!(client.send("PING"), "PONG");
+}
+
+#[test]
+fn idle_watchers_are_notified_right_away() {
+    let server = Server::start(&[]);
+    let mut watcher = server.connect();
+    let mut writer = server.connect();
+
+    assert_eq!(watcher.send("WATCH k"), "OK Watching k");
+    assert_eq!(writer.send("SET k 1"), "OK");
+    // The watcher hasn't sent anything since WATCH.
+    assert_eq!(watcher.read_line(), "WATCH k set");
+    assert_eq!(writer.send("DELETE k"), "OK Deleted k");
+    assert_eq!(watcher.read_line(), "WATCH k deleted");
+}
+
+#[test]
+fn own_notifications_follow_the_response() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+
+    client.send("WATCH k");
+    assert_eq!(client.send("SET k 1"), "OK");
+    assert_eq!(client.read_line(), "WATCH k set");
+    assert_eq!(client.send("GET k"), "k: 1");
+}
+
+#[test]
+fn idle_clients_are_dropped_after_the_timeout() {
+    let server = Server::start(&["--client-timeout", "0.2"]);
+    let mut idle = server.connect();
+    let mut busy = server.connect();
+
+    // Each command restarts the timer, so this outlasts it.
+    for _ in 0..4 {
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(busy.send("PING"), "PONG");
+    }
+    // Nothing was sent on this one, so the server has hung up.
+    assert_eq!(idle.read_line(), "");
+}
+
+#[test]
+fn open_connections_are_served_side_by_side() {
+    let server = Server::start(&[]);
+    let mut a = server.connect();
+    let mut b = server.connect();
+
+    assert_eq!(a.send("SET k 1"), "OK");
+    assert_eq!(b.send("GET k"), "k: 1");
+    assert_eq!(b.send("INCR k"), "2");
+    assert_eq!(a.send("GET k"), "k: 2");
+
+    // Each connection has its own selected database.
+    assert_eq!(b.send("SELECT 1"), "OK");
+    assert_eq!(b.send("GET k"), "ERR Key not found");
+    assert_eq!(a.send("GET k"), "k: 2");
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm_writes_the_autosave_file() {
+    let path = std::env::temp_dir().join(format!("pillsdb-{}-sigterm", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+
+    let mut server = Server::start(&["--autosave", path, "--autosave-interval", "3600"]);
+    let mut client = server.connect();
+    assert_eq!(client.send("SET k 1"), "OK");
+
+    let killed = Command::new("kill")
+        .args(["-TERM", &server.child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    assert!(server.child.wait().unwrap().success());
+
+    let mut loaded = Command::new(env!("CARGO_BIN_EXE_tests"))
+        .args(["--load", path])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    loaded.stdin.take().unwrap().write_all(b"GET k\n").unwrap();
+    let output = loaded.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "k: 1\n");
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn status_counts_the_callers_own_database() {
+    let server = Server::start(&[]);
+    let mut a = server.connect();
+    let mut b = server.connect();
+    assert_eq!(a.send("SELECT 1"), "OK");
+    assert_eq!(a.send("SET x 1"), "OK");
+    assert_eq!(a.send("SET y 2"), "OK");
+
+    assert_eq!(b.send("STATUS"), "keys: 0");
+    // The rest of the report.
+    for _ in 0..3 {
+        b.read_line();
+    }
+    assert_eq!(a.send("STATUS"), "keys: 2");
+}